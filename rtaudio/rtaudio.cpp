@@ -1,6 +1,5 @@
 #include <cstdio>
 #include <cstring>
-#include <iostream>
 
 #if defined(__APPLE__)
 #define __MACOSX_CORE__
@@ -28,10 +27,10 @@ rtaudio_static_inline void _warn(const char *file, int line, const char *msg) {
 #define rtaudio_warn(msg) _warn(__FILE__, __LINE__, msg)
 
 
-constexpr uint32_t CHANNEL_COUNT = 1;
 constexpr uint32_t SAMPLE_FORMAT = RTAUDIO_FORMAT_SINT16;
-constexpr uint32_t SAMPLE_RATE = 48000;
 constexpr uint32_t BUFFER_SIZE = 16;
+constexpr uint32_t DEVICE_SAMPLE_RATES = 16;
+constexpr uint32_t DEVICE_NAME_LENGTH = 512;
 
 
 typedef void (*rust_callback)(void *data, int16_t *, size_t);
@@ -41,6 +40,20 @@ struct Stream {
     rtaudio_t audio;
     rust_callback inner;
     void *data;
+    uint32_t channels;
+};
+
+struct DeviceInfo {
+    uint32_t probed;
+    uint32_t output_channels;
+    uint32_t input_channels;
+    uint32_t duplex_channels;
+    uint32_t is_default_output;
+    uint32_t is_default_input;
+    uint32_t native_formats;
+    uint32_t preferred_sample_rate;
+    uint32_t sample_rates[DEVICE_SAMPLE_RATES];
+    char name[DEVICE_NAME_LENGTH];
 };
 
 rtaudio_static_inline void rtaudio_check_stream_status(rtaudio_stream_status_t status) {
@@ -67,9 +80,9 @@ int output_callback(void *out_buffer_, void *in_buffer, unsigned int size, doubl
     auto *out_buffer = reinterpret_cast<int16_t *>(out_buffer_);
     rtaudio_check_stream_status(status);
 
-    memset(out_buffer, 0, size * sizeof(int16_t));
+    memset(out_buffer, 0, size * userdata->channels * sizeof(int16_t));
 
-    userdata->inner(userdata->data, out_buffer, size);
+    userdata->inner(userdata->data, out_buffer, size * userdata->channels);
 
     return 0;
 }
@@ -82,61 +95,12 @@ int input_callback(void *out_buffer, void *in_buffer_, unsigned int size, double
     auto *in_buffer = reinterpret_cast<int16_t *>(in_buffer_);
     rtaudio_check_stream_status(status);
 
-    userdata->inner(userdata->data, in_buffer, size);
+    userdata->inner(userdata->data, in_buffer, size * userdata->channels);
 
     return 0;
 }
 
 extern "C" {
-void print_device(rtaudio_device_info_t &device) {
-    if (device.probed) {
-        std::cout << "device: \"" << device.name << "\"";
-        if (device.is_default_output) {
-            std::cout << " <default output>";
-        }
-        if (device.is_default_input) {
-            std::cout << " <default input>";
-        }
-        std::cout << std::endl;
-        std::cout << "\tmaximum output channels: " << device.output_channels << ",\n";
-        std::cout << "\tmaximum input channels: " << device.input_channels << ",\n";
-        std::cout << "\tmaximum duplex channels: " << device.duplex_channels << ",\n";
-        std::cout << "\tsample rate:";
-        for (auto sample_rate: device.sample_rates) {
-            if (sample_rate == 0) { break; }
-            std::cout << ' ' << sample_rate;
-        }
-        std::cout << ",\n";
-        std::cout << "\tpreferredSampleRate: " << device.preferred_sample_rate << ",\n";
-        std::cout << "\tnativeFormats: ";
-
-        switch (device.native_formats) {
-            case RTAUDIO_FORMAT_SINT8:
-                std::cout << "i8";
-                break;
-            case RTAUDIO_FORMAT_SINT16:
-                std::cout << "i16";
-                break;
-            case RTAUDIO_FORMAT_SINT24:
-                std::cout << "i24";
-                break;
-            case RTAUDIO_FORMAT_SINT32:
-                std::cout << "i32";
-                break;
-            case RTAUDIO_FORMAT_FLOAT32:
-                std::cout << "f32";
-                break;
-            case RTAUDIO_FORMAT_FLOAT64:
-                std::cout << "f64";
-                break;
-            default:
-                std::cout << "unknown";
-        }
-
-        std::cout << ".\n";
-    }
-}
-
 rtaudio_static_inline rtaudio_t rtaudio_select_host() {
 #if defined(__APPLE__)
     return rtaudio_create(RTAUDIO_API_UNSPECIFIED);
@@ -147,18 +111,40 @@ rtaudio_static_inline rtaudio_t rtaudio_select_host() {
 #endif
 }
 
-rtaudio_unused void rtaudio_print_hosts() {
+rtaudio_static_inline void copy_device_info(DeviceInfo &device, rtaudio_device_info_t &info) {
+    memset(&device, 0, sizeof(DeviceInfo));
+
+    device.probed = info.probed;
+    device.output_channels = info.output_channels;
+    device.input_channels = info.input_channels;
+    device.duplex_channels = info.duplex_channels;
+    device.is_default_output = info.is_default_output;
+    device.is_default_input = info.is_default_input;
+    device.native_formats = info.native_formats;
+    device.preferred_sample_rate = info.preferred_sample_rate;
+
+    uint32_t count = sizeof(info.sample_rates) / sizeof(info.sample_rates[0]);
+
+    for (uint32_t i = 0; i < count && i < DEVICE_SAMPLE_RATES; ++i) {
+        if (info.sample_rates[i] == 0) { break; }
+        device.sample_rates[i] = info.sample_rates[i];
+    }
+
+    strncpy(device.name, info.name, DEVICE_NAME_LENGTH - 1);
+}
+
+rtaudio_unused int32_t rtaudio_get_devices(DeviceInfo *devices, int32_t capacity) {
     rtaudio_t rtaudio = rtaudio_select_host();
     int32_t count = rtaudio_device_count(rtaudio);
 
-    std::cout << "Host: " << rtaudio_api_display_name(rtaudio_current_api(rtaudio)) << std::endl;
-
-    for (int32_t i = 0; i < count; ++i) {
+    for (int32_t i = 0; i < count && i < capacity; ++i) {
         rtaudio_device_info_t device_info = rtaudio_get_device_info(rtaudio, i);
-        print_device(device_info);
+        copy_device_info(devices[i], device_info);
     }
 
     rtaudio_destroy(rtaudio);
+
+    return count;
 }
 
 void rtaudio_destroy_stream(Stream *stream) {
@@ -168,17 +154,20 @@ void rtaudio_destroy_stream(Stream *stream) {
     delete stream;
 }
 
-rtaudio_unused Stream *rtaudio_create_output_stream(rust_callback callback, void *data) {
+rtaudio_unused Stream *rtaudio_create_output_stream(rust_callback callback, void *data,
+                                                     uint32_t sample_rate, uint32_t channels,
+                                                     int32_t device_index) {
     rtaudio_t rtaudio = rtaudio_select_host();
-    uint32_t device = rtaudio_get_default_output_device(rtaudio);
+    uint32_t device = device_index < 0 ? rtaudio_get_default_output_device(rtaudio)
+                                       : static_cast<uint32_t>(device_index);
 
-    rtaudio_stream_parameters_t config{device, CHANNEL_COUNT, 0};
+    rtaudio_stream_parameters_t config{device, channels, 0};
 
     uint32_t buffer_size = BUFFER_SIZE;
 
-    auto *stream = new Stream{rtaudio, callback, data};
+    auto *stream = new Stream{rtaudio, callback, data, channels};
 
-    if (rtaudio_open_stream(rtaudio, &config, nullptr, SAMPLE_FORMAT, SAMPLE_RATE, &buffer_size,
+    if (rtaudio_open_stream(rtaudio, &config, nullptr, SAMPLE_FORMAT, sample_rate, &buffer_size,
                             output_callback, stream, nullptr, nullptr)) { goto error; }
 
     if (rtaudio_start_stream(rtaudio)) { goto error; }
@@ -193,17 +182,20 @@ rtaudio_unused Stream *rtaudio_create_output_stream(rust_callback callback, void
     return nullptr;
 }
 
-rtaudio_unused Stream *rtaudio_create_input_stream(rust_callback callback, void *data) {
+rtaudio_unused Stream *rtaudio_create_input_stream(rust_callback callback, void *data,
+                                                    uint32_t sample_rate, uint32_t channels,
+                                                    int32_t device_index) {
     rtaudio_t rtaudio = rtaudio_select_host();
-    uint32_t device = rtaudio_get_default_input_device(rtaudio);
+    uint32_t device = device_index < 0 ? rtaudio_get_default_input_device(rtaudio)
+                                       : static_cast<uint32_t>(device_index);
 
-    rtaudio_stream_parameters_t config{device, CHANNEL_COUNT, 0};
+    rtaudio_stream_parameters_t config{device, channels, 0};
 
     uint32_t buffer_size = BUFFER_SIZE;
 
-    auto *stream = new Stream{rtaudio, callback, data};
+    auto *stream = new Stream{rtaudio, callback, data, channels};
 
-    if (rtaudio_open_stream(rtaudio, nullptr, &config, SAMPLE_FORMAT, SAMPLE_RATE, &buffer_size,
+    if (rtaudio_open_stream(rtaudio, nullptr, &config, SAMPLE_FORMAT, sample_rate, &buffer_size,
                             input_callback, stream, nullptr, nullptr)) { goto error; }
 
     if (rtaudio_start_stream(rtaudio)) { goto error; }
@@ -0,0 +1,15 @@
+pub const SAMPLE_RATE: u32 = 48000;
+
+
+pub trait SampleBackend {
+    type Stream;
+    type Error: std::error::Error + 'static;
+
+    fn create_input_stream<F>(&self, sample_rate: u32, channels: u32, callback: F)
+                              -> Result<Self::Stream, Self::Error>
+        where F: FnMut(&mut [i16]) + Send + 'static;
+
+    fn create_output_stream<F>(&self, sample_rate: u32, channels: u32, callback: F)
+                               -> Result<Self::Stream, Self::Error>
+        where F: FnMut(&mut [i16]) + Send + 'static;
+}
@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use crate::athernet::physical::PhyConfig;
+
+
+#[derive(Clone)]
+pub struct Channel {
+    gain: f32,
+    snr: Option<f32>,
+    signal_power: f32,
+    delay: usize,
+    echoes: Vec<(usize, f32)>,
+    seed: u64,
+    rng: StdRng,
+    history: VecDeque<f32>,
+}
+
+fn parse<T: std::str::FromStr>(item: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("invalid value in channel option: {:?}", item))
+}
+
+impl Channel {
+    pub fn new(config: &PhyConfig) -> Self {
+        Self {
+            gain: 1.,
+            snr: None,
+            signal_power: config.signal_power(),
+            delay: 0,
+            echoes: Vec::new(),
+            seed: 0,
+            rng: StdRng::seed_from_u64(0),
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn parse(spec: &str, config: &PhyConfig) -> Result<Self, String> {
+        let mut channel = Channel::new(config);
+
+        for item in spec.split(',') {
+            let (key, value) = match item.find(':') {
+                Some(index) => (&item[..index], &item[index + 1..]),
+                None => return Err(format!("missing value in channel option: {:?}", item)),
+            };
+
+            match key {
+                "gain" => { channel.set_gain(parse(item, value)?); }
+                "snr" => { channel.set_snr(parse(item, value)?); }
+                "delay" => { channel.set_delay(parse(item, value)?); }
+                "seed" => { channel.set_seed(parse(item, value)?); }
+                "echo" => match value.find('/') {
+                    Some(index) => {
+                        channel.add_echo(
+                            parse(item, &value[..index])?, parse(item, &value[index + 1..])?,
+                        );
+                    }
+                    None => return Err(format!("echo needs delay/gain: {:?}", item)),
+                },
+                _ => return Err(format!("unknown channel option: {:?}", item)),
+            }
+        }
+
+        Ok(channel)
+    }
+
+    pub fn set_gain(&mut self, gain: f32) -> &mut Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn set_snr(&mut self, snr: f32) -> &mut Self {
+        self.snr = Some(snr);
+        self
+    }
+
+    pub fn set_signal_power(&mut self, signal_power: f32) -> &mut Self {
+        self.signal_power = signal_power;
+        self
+    }
+
+    pub fn measure_power(samples: &[i16], block_len: usize) -> f32 {
+        let powers = samples.chunks(std::cmp::max(block_len, 1)).map(|block| {
+            let energy = block.iter().map(|item| *item as f32 * *item as f32).sum::<f32>();
+            energy / block.len() as f32
+        }).collect::<Vec<_>>();
+
+        let loudest = powers.iter().cloned().fold(0., f32::max);
+        let active = powers.into_iter().filter(|power| *power * 4. >= loudest)
+            .collect::<Vec<_>>();
+
+        if active.is_empty() { 0. } else { active.iter().sum::<f32>() / active.len() as f32 }
+    }
+
+    pub fn set_delay(&mut self, delay: usize) -> &mut Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn add_echo(&mut self, delay: usize, gain: f32) -> &mut Self {
+        self.echoes.push((delay, gain));
+        self
+    }
+
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn fork(&self, index: u64) -> Self {
+        let mut result = self.clone();
+        result.set_seed(self.seed.wrapping_add(index));
+        result.history.clear();
+        result
+    }
+
+    fn history_len(&self) -> usize {
+        self.delay + self.echoes.iter().map(|(delay, _)| *delay).max().unwrap_or(0) + 1
+    }
+
+    fn gaussian(&mut self) -> f32 {
+        let u1 = 1. - self.rng.gen::<f32>();
+        let u2 = self.rng.gen::<f32>();
+
+        (-2. * u1.ln()).sqrt() * (2. * std::f32::consts::PI * u2).cos()
+    }
+
+    fn noise_deviation(&self) -> f32 {
+        match self.snr {
+            Some(snr) => self.gain * (self.signal_power / 10f32.powf(snr / 10.)).sqrt(),
+            None => 0.,
+        }
+    }
+
+    pub fn push_back(&mut self, item: f32) -> i16 {
+        let len = self.history_len();
+        while self.history.len() < len { self.history.push_front(0.); }
+        self.history.pop_front();
+        self.history.push_back(item);
+
+        let last = len - 1 - self.delay;
+
+        let signal = self.history[last] + self.echoes.iter().map(|(delay, gain)| {
+            self.history[last - delay] * gain
+        }).sum::<f32>();
+
+        let deviation = self.noise_deviation();
+        let noise = if deviation > 0. { self.gaussian() * deviation } else { 0. };
+        let value = signal * self.gain + noise;
+
+        value.max(i16::MIN as f32).min(i16::MAX as f32) as i16
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::athernet::testing::{payload, receive, received, trace, SEEDS};
+
+    fn sweep(spec: &str) -> usize {
+        received(&PhyConfig::default(), &format!("gain:0.0625,{}", spec), 100, 0.)
+    }
+
+    #[test]
+    fn parse() {
+        let config = PhyConfig::default();
+        let parse = |spec| Channel::parse(spec, &config);
+        let channel = parse("gain:0.5,snr:10,delay:400,echo:120/0.3,seed:7").unwrap();
+
+        assert_eq!(channel.gain, 0.5);
+        assert_eq!(channel.snr, Some(10.));
+        assert_eq!(channel.delay, 400);
+        assert_eq!(channel.echoes, vec![(120, 0.3)]);
+        assert_eq!(channel.seed, 7);
+
+        assert_eq!(channel.signal_power, config.signal_power());
+
+        assert!(parse("snr").is_err());
+        assert!(parse("snr:loud").is_err());
+        assert!(parse("echo:120").is_err());
+        assert!(parse("noise:10").is_err());
+    }
+
+    #[test]
+    fn snr_sweep() {
+        for snr in [40., 20., 12.].iter() {
+            assert_eq!(sweep(&format!("snr:{}", snr)), SEEDS, "snr {}", snr);
+        }
+
+        assert_eq!(sweep("snr:-10"), 0);
+    }
+
+    #[test]
+    fn delay_sweep() {
+        for delay in [0, 1, 400, 2400].iter() {
+            assert_eq!(sweep(&format!("snr:30,delay:{}", delay)), SEEDS, "delay {}", delay);
+        }
+    }
+
+    #[test]
+    fn echo_sweep() {
+        for (delay, gain) in [(5, 0.3), (40, 0.3), (200, 0.5)].iter() {
+            let spec = format!("snr:30,echo:{}/{}", delay, gain);
+            assert_eq!(sweep(&spec), SEEDS, "echo {}", delay);
+        }
+    }
+
+    #[test]
+    fn measured_signal_power() {
+        let config = PhyConfig { symbol_len: 8, ..PhyConfig::default() };
+        let payload = payload(100);
+        let samples = trace(&config, &payload, 16);
+
+        let power = Channel::measure_power(&samples, config.symbol_len * 16);
+        let ratio = power * 256. / config.signal_power();
+        assert!(ratio > 0.5 && ratio < 2., "ratio {}", ratio);
+
+        for seed in 0..SEEDS as u64 {
+            let mut channel = Channel::parse("snr:12", &config).unwrap();
+            channel.set_seed(seed).set_signal_power(power);
+
+            let samples = samples.iter().map(|item| *item as f32);
+            assert_eq!(receive(&config, &mut channel, samples).as_deref(), Some(&payload[..]));
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::ops::{Add, Div, Mul, Sub};
+
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    #[inline]
+    pub fn new(re: f32, im: f32) -> Self { Self { re, im } }
+
+    #[inline]
+    pub fn from_polar(norm: f32, arg: f32) -> Self { Self::new(norm * arg.cos(), norm * arg.sin()) }
+
+    #[inline]
+    pub fn conj(self) -> Self { Self::new(self.re, -self.im) }
+
+    #[inline]
+    pub fn norm_sqr(self) -> f32 { self.re * self.re + self.im * self.im }
+
+    #[inline]
+    pub fn arg(self) -> f32 { self.im.atan2(self.re) }
+
+    #[inline]
+    pub fn scale(self, value: f32) -> Self { Self::new(self.re * value, self.im * value) }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: Self) -> Self { Self::new(self.re + rhs.re, self.im + rhs.im) }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: Self) -> Self { Self::new(self.re - rhs.re, self.im - rhs.im) }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(self.re * rhs.re - self.im * rhs.im, self.re * rhs.im + self.im * rhs.re)
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    #[inline]
+    fn div(self, rhs: Self) -> Self {
+        let norm = rhs.norm_sqr();
+
+        if norm == 0. { Self::default() } else { (self * rhs.conj()).scale(1. / norm) }
+    }
+}
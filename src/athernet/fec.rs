@@ -0,0 +1,129 @@
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Fec {
+    None,
+    Hamming,
+}
+
+impl Fec {
+    pub fn encode<I>(self, iter: I) -> Box<dyn Iterator<Item=bool> + Send>
+        where I: Iterator<Item=bool> + Send + 'static
+    {
+        match self {
+            Fec::None => Box::new(iter),
+            Fec::Hamming => Box::new(HammingEncode { iter, buffer: [false; 7], index: 7 }),
+        }
+    }
+}
+
+
+struct HammingEncode<I> {
+    iter: I,
+    buffer: [bool; 7],
+    index: usize,
+}
+
+impl<I: Iterator<Item=bool>> Iterator for HammingEncode<I> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == 7 {
+            let mut data = [false; 4];
+
+            for (index, bit) in data.iter_mut().enumerate() {
+                match self.iter.next() {
+                    Some(item) => *bit = item,
+                    None if index == 0 => return None,
+                    None => break,
+                }
+            }
+
+            let [d1, d2, d3, d4] = data;
+
+            self.buffer = [d1 ^ d2 ^ d4, d1 ^ d3 ^ d4, d1, d2 ^ d3 ^ d4, d2, d3, d4];
+            self.index = 0;
+        }
+
+        let index = self.index;
+        self.index += 1;
+        Some(self.buffer[index])
+    }
+}
+
+
+#[derive(Copy, Clone)]
+pub struct FecDecoder {
+    fec: Fec,
+    buffer: [bool; 7],
+    count: usize,
+}
+
+impl FecDecoder {
+    pub fn new(fec: Fec) -> Self { Self { fec, buffer: [false; 7], count: 0 } }
+
+    pub fn push(&mut self, bit: bool) -> Option<([bool; 4], usize)> {
+        match self.fec {
+            Fec::None => Some(([bit, false, false, false], 1)),
+            Fec::Hamming => {
+                self.buffer[self.count] = bit;
+                self.count += 1;
+
+                if self.count < 7 { return None; }
+
+                self.count = 0;
+
+                let syndrome = (0..7).filter(|index| self.buffer[*index])
+                    .fold(0, |syndrome, index| syndrome ^ (index + 1));
+
+                if syndrome != 0 { self.buffer[syndrome - 1] ^= true; }
+
+                Some(([self.buffer[2], self.buffer[4], self.buffer[5], self.buffer[6]], 4))
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(fec: Fec, bits: &[bool]) -> Vec<bool> {
+        let mut decoder = FecDecoder::new(fec);
+
+        bits.iter().filter_map(|bit| decoder.push(*bit))
+            .flat_map(|(bits, size)| bits[..size].to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = (0..64).map(|index| (index * 7) % 3 == 0).collect::<Vec<_>>();
+
+        for fec in [Fec::None, Fec::Hamming].iter() {
+            let encoded = fec.encode(data.clone().into_iter()).collect::<Vec<_>>();
+            assert_eq!(decode(*fec, &encoded), data, "{:?}", fec);
+        }
+
+        let encoded = Fec::Hamming.encode(data.clone().into_iter()).collect::<Vec<_>>();
+        assert_eq!(encoded.len(), data.len() / 4 * 7);
+
+        let odd = Fec::Hamming.encode(data.clone().into_iter().take(6)).collect::<Vec<_>>();
+        assert_eq!(odd.len(), 14);
+        assert_eq!(decode(Fec::Hamming, &odd)[..6], data[..6]);
+    }
+
+    #[test]
+    fn corrects_single_bit_errors() {
+        for value in 0..16u8 {
+            let data = (0..4).map(|index| (value >> index) & 1 == 1).collect::<Vec<_>>();
+            let codeword = Fec::Hamming.encode(data.clone().into_iter()).collect::<Vec<_>>();
+
+            for error in 0..7 {
+                let mut corrupted = codeword.clone();
+                corrupted[error] ^= true;
+
+                assert_eq!(decode(Fec::Hamming, &corrupted), data, "{} {}", value, error);
+            }
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::VecDeque, thread, time::{Duration, Instant},
+    sync::{Arc, Mutex, Weak},
+};
+use crate::athernet::{
+    backend::{SampleBackend, SAMPLE_RATE}, channel::Channel, physical::PhyConfig,
+};
+
+
+const BUFFER_SIZE: usize = 16;
+const DEFAULT_GAIN: f32 = 1. / 16.;
+
+
+type Callback = Box<dyn FnMut(&mut [i16]) + Send>;
+
+#[derive(std::fmt::Debug)]
+pub enum LoopbackError {
+    FormatMismatch { sample_rate: u32, channels: usize },
+}
+
+impl std::fmt::Display for LoopbackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoopbackError::FormatMismatch { sample_rate, channels } => write!(
+                f, "loopback medium already runs at {} Hz with {} channels!",
+                sample_rate, channels,
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoopbackError {}
+
+struct Medium {
+    inputs: Vec<(usize, Callback, Vec<Channel>)>,
+    outputs: Vec<(usize, Callback)>,
+    playback: VecDeque<i16>,
+    next_id: usize,
+    format: Option<(u32, usize)>,
+    channel: Channel,
+}
+
+impl Medium {
+    fn new(channel: Channel) -> Self {
+        Self {
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            playback: VecDeque::new(),
+            next_id: 0,
+            format: None,
+            channel,
+        }
+    }
+
+    fn format(&self) -> (u32, usize) { self.format.unwrap_or((SAMPLE_RATE, 1)) }
+
+    fn insert(&mut self, input: bool, sample_rate: u32, channels: usize, callback: Callback)
+              -> Result<usize, LoopbackError> {
+        match self.format {
+            Some((rate, count)) if (rate, count) != (sample_rate, channels) => {
+                return Err(LoopbackError::FormatMismatch { sample_rate: rate, channels: count });
+            }
+            _ => self.format = Some((sample_rate, channels)),
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        if input {
+            let channels = (0..channels)
+                .map(|index| self.channel.fork(id as u64 + ((index as u64) << 32)))
+                .collect();
+
+            self.inputs.push((id, callback, channels));
+        } else {
+            self.outputs.push((id, callback));
+        }
+
+        Ok(id)
+    }
+
+    fn remove(&mut self, id: usize) {
+        self.inputs.retain(|(item, _, _)| *item != id);
+        self.outputs.retain(|(item, _)| *item != id);
+
+        if self.inputs.is_empty() && self.outputs.is_empty() { self.format = None; }
+    }
+
+    fn tick(&mut self, mix: &mut [f32], play: &mut [i16], buffer: &mut [i16]) {
+        for item in mix.iter_mut() { *item = 0.; }
+
+        for item in play.iter_mut() { *item = self.playback.pop_front().unwrap_or(0); }
+
+        for (_, callback) in self.outputs.iter_mut() {
+            for sample in buffer.iter_mut() { *sample = 0; }
+
+            callback(buffer);
+
+            for (sum, sample) in mix.iter_mut().zip(buffer.iter()) {
+                *sum += *sample as f32;
+            }
+        }
+
+        for (_, callback, channels) in self.inputs.iter_mut() {
+            let count = channels.len();
+
+            for (index, ((sample, sum), played)) in buffer.iter_mut()
+                .zip(mix.iter()).zip(play.iter()).enumerate() {
+                *sample = channels[index % count].push_back(*sum).saturating_add(*played);
+            }
+
+            callback(buffer);
+        }
+    }
+}
+
+fn run(medium: Weak<Mutex<Medium>>) {
+    let mut mix = Vec::new();
+    let mut play = Vec::new();
+    let mut buffer = Vec::new();
+    let start = Instant::now();
+    let mut sample_count = 0u64;
+
+    while let Some(medium) = medium.upgrade() {
+        let sample_rate = {
+            let mut medium = medium.lock().unwrap();
+            let (sample_rate, channels) = medium.format();
+            let size = BUFFER_SIZE * channels;
+
+            mix.resize(size, 0.);
+            play.resize(size, 0);
+            buffer.resize(size, 0);
+
+            medium.tick(&mut mix, &mut play, &mut buffer);
+            sample_rate as u64
+        };
+        drop(medium);
+
+        sample_count += BUFFER_SIZE as u64;
+
+        let target = Duration::from_micros(sample_count * 1_000_000 / sample_rate);
+        let elapsed = start.elapsed();
+
+        if target > elapsed { thread::sleep(target - elapsed); }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct Loopback {
+    inner: Arc<Mutex<Medium>>,
+}
+
+impl Loopback {
+    pub fn new() -> Self {
+        let mut channel = Channel::new(&PhyConfig::default());
+        channel.set_gain(DEFAULT_GAIN);
+        Self::with_channel(channel)
+    }
+
+    pub fn with_channel(channel: Channel) -> Self {
+        let inner = Arc::new(Mutex::new(Medium::new(channel)));
+        let medium = Arc::downgrade(&inner);
+
+        thread::spawn(move || run(medium));
+
+        Self { inner }
+    }
+
+    pub fn play(&self, samples: &[i16]) {
+        self.inner.lock().unwrap().playback.extend(samples.iter().cloned());
+    }
+
+    fn create_stream(&self, input: bool, sample_rate: u32, channels: u32, callback: Callback)
+                     -> Result<LoopbackStream, LoopbackError> {
+        let channels = std::cmp::max(channels, 1) as usize;
+        let id = self.inner.lock().unwrap().insert(input, sample_rate, channels, callback)?;
+
+        Ok(LoopbackStream { inner: self.inner.clone(), id })
+    }
+}
+
+impl SampleBackend for Loopback {
+    type Stream = LoopbackStream;
+    type Error = LoopbackError;
+
+    fn create_input_stream<F>(&self, sample_rate: u32, channels: u32, callback: F)
+                              -> Result<Self::Stream, Self::Error>
+        where F: FnMut(&mut [i16]) + Send + 'static
+    {
+        self.create_stream(true, sample_rate, channels, Box::new(callback))
+    }
+
+    fn create_output_stream<F>(&self, sample_rate: u32, channels: u32, callback: F)
+                               -> Result<Self::Stream, Self::Error>
+        where F: FnMut(&mut [i16]) + Send + 'static
+    {
+        self.create_stream(false, sample_rate, channels, Box::new(callback))
+    }
+}
+
+pub struct LoopbackStream {
+    inner: Arc<Mutex<Medium>>,
+    id: usize,
+}
+
+impl Drop for LoopbackStream {
+    fn drop(&mut self) { self.inner.lock().unwrap().remove(self.id); }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::athernet::{MacLayer, mac::MacConfig};
+
+    fn packs(count: u8, size: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|index| (0..size).map(|item| item as u8 ^ index).collect()).collect()
+    }
+
+    fn pair(medium: &Loopback, config: PhyConfig) -> (MacLayer<Loopback>, MacLayer<Loopback>) {
+        (
+            MacLayer::with_backend(medium, 1, false, config.clone()).unwrap(),
+            MacLayer::with_backend(medium, 2, false, config).unwrap(),
+        )
+    }
+
+    fn transfer(a: &mut MacLayer<Loopback>, b: &mut MacLayer<Loopback>, packs: &[Vec<u8>]) {
+        let deliveries: Vec<_> = packs.iter().map(|pack| a.send(pack, 2).unwrap()).collect();
+
+        for pack in packs.iter() { assert_eq!(&*b.recv(1).unwrap(), &pack[..]); }
+
+        assert!(deliveries.iter().all(|item| item.wait()));
+    }
+
+    #[test]
+    fn send_recv_ping() {
+        let medium = Loopback::new();
+        let (mut a, mut b) = pair(&medium, PhyConfig::default());
+
+        transfer(&mut a, &mut b, &packs(4, 100));
+        assert!(a.get_rtt(2).is_some());
+
+        assert!(a.ping(2).unwrap().is_some());
+        assert!(b.ping(1).unwrap().is_some());
+        assert!(a.ping(3).unwrap().is_none());
+
+        for pack in packs(3, 50).iter() {
+            assert!(a.ping(2).unwrap().is_some());
+            transfer(&mut a, &mut b, std::slice::from_ref(pack));
+        }
+    }
+
+    #[test]
+    fn send_after_retry_limit() {
+        let medium = Loopback::new();
+        let mac_config = MacConfig { max_retries: 2, ..MacConfig::default() };
+        let mut a = MacLayer::with_config(&medium, 1, false, PhyConfig::default(), mac_config)
+            .unwrap();
+
+        assert!(!a.send(&[0xff; 50], 2).unwrap().wait());
+
+        let mut b = MacLayer::with_backend(&medium, 2, false, PhyConfig::default()).unwrap();
+
+        transfer(&mut a, &mut b, &packs(10, 50));
+    }
+
+    #[test]
+    fn two_channel_links() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let medium = Loopback::new();
+        let (mut a, mut b) = pair(&medium, PhyConfig { channels: 2, ..PhyConfig::default() });
+
+        let overlap = Arc::new(AtomicUsize::new(0));
+        let counter = overlap.clone();
+        let _listener = medium.create_input_stream(SAMPLE_RATE, 2, move |data: &mut [i16]| {
+            let both = data.chunks_exact(2)
+                .filter(|pair| pair.iter().all(|sample| sample.abs() > 64)).count();
+            counter.fetch_add(both, Ordering::SeqCst);
+        }).unwrap();
+
+        transfer(&mut a, &mut b, &packs(8, 100));
+        assert!(overlap.load(Ordering::SeqCst) > 5000);
+    }
+
+    #[test]
+    fn rto_covers_large_frames() {
+        let medium = Loopback::new();
+        let config = PhyConfig { payload_max: 1520, ..PhyConfig::default() };
+        let (mut a, mut b) = pair(&medium, config);
+        let mtu = a.get_mtu();
+
+        transfer(&mut a, &mut b, &packs(8, mtu));
+
+        let rtt = a.get_rtt(2).unwrap();
+        assert!(rtt.rto >= rtt.srtt, "{:?}", rtt);
+    }
+
+    #[test]
+    fn mismatched_format() {
+        let medium = Loopback::new();
+        let stereo = PhyConfig { channels: 2, ..PhyConfig::default() };
+        let slow = PhyConfig { sample_rate: 44100, ..PhyConfig::default() };
+
+        let a = MacLayer::with_backend(&medium, 1, false, PhyConfig::default()).unwrap();
+        assert!(MacLayer::with_backend(&medium, 2, false, stereo.clone()).is_err());
+        assert!(MacLayer::with_backend(&medium, 2, false, slow).is_err());
+        assert!(MacLayer::with_backend(&medium, 2, false, PhyConfig::default()).is_ok());
+
+        drop(a);
+        assert!(MacLayer::with_backend(&medium, 2, false, stereo).is_ok());
+    }
+
+    #[test]
+    fn payload_max_too_small() {
+        let medium = Loopback::new();
+        let config = |payload_max| PhyConfig { payload_max, ..PhyConfig::default() };
+
+        assert!(MacLayer::with_backend(&medium, 1, false, config(0)).is_err());
+        assert!(MacLayer::with_backend(&medium, 1, false, config(10)).is_err());
+        assert_eq!(MacLayer::with_backend(&medium, 1, false, config(11)).unwrap().get_mtu(), 1);
+    }
+}
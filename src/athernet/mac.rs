@@ -1,47 +1,74 @@
+use std::fmt::Formatter;
 use crate::{
-    athernet::physical::{PHY_PAYLOAD_MAX, PhyPayload},
-    utils::{crc16_checksum, crc8_checksum}
+    athernet::physical::{Abort, PhyPayload},
+    utils::{crc16_checksum, crc8_checksum, slice_to_le_u16}
 };
 
 
 pub const CRC_SIZE: usize = 2;
-pub const MAC_PAYLOAD_MAX: usize = PHY_PAYLOAD_MAX - MacFrame::MAC_DATA_SIZE - CRC_SIZE;
-
-pub type MacPayload = [u8; MAC_PAYLOAD_MAX];
 
 pub type MacAddress = u8;
 
-#[derive(Copy, Clone)]
+#[derive(Clone, PartialEq, Debug)]
+pub struct MacConfig {
+    pub window_size: usize,
+    pub max_retries: usize,
+}
+
+impl Default for MacConfig {
+    fn default() -> Self {
+        Self {
+            window_size: 4,
+            max_retries: 16,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct MacFrame {
     inner: PhyPayload,
 }
 
 impl MacFrame {
-    pub const MAC_INDEX: usize = 0;
-    pub const OP_INDEX: usize = Self::MAC_INDEX + 1;
+    pub const SRC_INDEX: usize = 0;
+    pub const DEST_INDEX: usize = Self::SRC_INDEX + 1;
+    pub const VERSION_INDEX: usize = Self::DEST_INDEX + 1;
+    pub const OP_INDEX: usize = Self::VERSION_INDEX + 1;
     pub const MAC_DATA_SIZE: usize = Self::OP_INDEX + 1;
+    pub const BASE_INDEX: usize = Self::MAC_DATA_SIZE;
+    pub const LENGTH_INDEX: usize = Self::BASE_INDEX + 1;
+    pub const LENGTH_SIZE: usize = 2;
+    pub const HEADER_CRC_INDEX: usize = Self::LENGTH_INDEX + Self::LENGTH_SIZE;
+    pub const MAC_HEADER_SIZE: usize = Self::HEADER_CRC_INDEX + 1;
+    pub const BITMAP_INDEX: usize = Self::MAC_DATA_SIZE;
+    pub const BITMAP_SIZE: usize = 2;
+
+    pub const VERSION: u8 = 2;
 
-    pub const BROADCAST_MAC: u8 = 0b1111;
+    pub const BROADCAST_MAC: u8 = u8::MAX;
 
     pub const OP_DATA: u8 = 0b0000;
     pub const OP_PING_REQ: u8 = 0b0001;
     pub const OP_PING_REPLY: u8 = 0b0010;
+    pub const OP_BLOCK_ACK: u8 = 0b0011;
     pub const OP_ACK: u8 = 0b1111;
 
     #[inline]
-    pub fn new() -> Self { Self { inner: [0u8; PHY_PAYLOAD_MAX] } }
+    pub fn new() -> Self {
+        let mut inner = vec![0u8; Self::MAC_DATA_SIZE];
+        inner[Self::VERSION_INDEX] = Self::VERSION;
+        Self { inner }
+    }
 
     #[inline]
     fn set_src(&mut self, val: MacAddress) -> &mut Self {
-        self.inner[Self::MAC_INDEX] &= 0b11110000;
-        self.inner[Self::MAC_INDEX] |= (val & 0b1111) << 0;
+        self.inner[Self::SRC_INDEX] = val;
         self
     }
 
     #[inline]
     fn set_dest(&mut self, val: MacAddress) -> &mut Self {
-        self.inner[Self::MAC_INDEX] &= 0b00001111;
-        self.inner[Self::MAC_INDEX] |= (val & 0b1111) << 4;
+        self.inner[Self::DEST_INDEX] = val;
         self
     }
 
@@ -59,45 +86,101 @@ impl MacFrame {
         self
     }
 
+    #[inline]
+    fn set_base(&mut self, val: u8) -> &mut Self {
+        self.inner.resize(std::cmp::max(self.inner.len(), Self::LENGTH_INDEX), 0);
+        self.inner[Self::BASE_INDEX] = val;
+        self
+    }
+
     #[inline]
     fn set_pay_load(&mut self, data: &[u8]) -> &mut Self {
-        let size = data.len();
-        self.inner[Self::MAC_DATA_SIZE] = size as u8;
-        self.inner[Self::MAC_DATA_SIZE + 1..][..size].copy_from_slice(&data[..size]);
+        self.inner.truncate(Self::LENGTH_INDEX);
+        self.inner.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        self.inner.push(0);
+        self.inner.extend_from_slice(data);
+        self
+    }
+
+    #[inline]
+    fn set_bitmap(&mut self, bitmap: u16) -> &mut Self {
+        self.inner.truncate(Self::BITMAP_INDEX);
+        self.inner.extend_from_slice(&bitmap.to_le_bytes());
         self
     }
 
     #[inline]
     fn generate_crc(&mut self) -> &mut Self {
         let size = self.get_size();
+        self.inner.truncate(size);
 
         if self.is_data() {
+            self.inner[Self::HEADER_CRC_INDEX] =
+                crc8_checksum(self.inner[..Self::HEADER_CRC_INDEX].iter().cloned());
+
             let crc = crc16_checksum(self.inner[..size].iter().cloned());
-            self.inner[size + 0] = ((crc >> 0) & 0b11111111) as u8;
-            self.inner[size + 1] = ((crc >> 8) & 0b11111111) as u8;
+            self.inner.push(((crc >> 0) & 0b11111111) as u8);
+            self.inner.push(((crc >> 8) & 0b11111111) as u8);
             self
         } else {
-            let size = self.get_size();
-            self.inner[size] = crc8_checksum(self.inner[..size].iter().cloned());
+            let crc = crc8_checksum(self.inner[..size].iter().cloned());
+            self.inner.push(crc);
             self
         }
     }
 
+    pub fn frame_size(header: &[u8]) -> Option<Result<usize, Abort>> {
+        let version = *header.get(Self::VERSION_INDEX)?;
+
+        if version != Self::VERSION { return Some(Err(Abort::Version(version))); }
+
+        if header.len() < Self::MAC_DATA_SIZE { return None; }
+
+        let op = header[Self::OP_INDEX] & 0b1111;
+
+        let header_size = match op {
+            Self::OP_DATA => Self::MAC_HEADER_SIZE,
+            Self::OP_BLOCK_ACK => Self::BITMAP_INDEX + Self::BITMAP_SIZE + 1,
+            _ => Self::MAC_DATA_SIZE + 1,
+        };
+
+        if header.len() < header_size { return None; }
+
+        if crc8_checksum(header[..header_size].iter().cloned()) != 0 {
+            return Some(Err(Abort::HeaderCrc));
+        }
+
+        if op == Self::OP_DATA {
+            let length = slice_to_le_u16(&header[Self::LENGTH_INDEX..][..Self::LENGTH_SIZE]);
+            Some(Ok(Self::MAC_HEADER_SIZE + length as usize + CRC_SIZE))
+        } else {
+            Some(Ok(header_size))
+        }
+    }
+
     #[inline]
-    pub fn new_data(src: u8, dest: u8, tag: u8, data: &[u8]) -> Self {
+    pub fn new_data(src: u8, dest: u8, sequence: u8, data: &[u8]) -> Self {
         let mut result = Self::new();
 
         result
             .set_src(src)
             .set_dest(dest)
             .set_op(Self::OP_DATA)
-            .set_tag(tag)
+            .set_tag(sequence)
+            .set_base(sequence)
             .set_pay_load(data)
             .generate_crc();
 
         result
     }
 
+    #[inline]
+    pub fn rebase(&self, base: u8) -> Self {
+        let mut result = self.clone();
+        result.set_base(base).generate_crc();
+        result
+    }
+
     #[inline]
     pub fn new_ack(src: u8, dest: u8, tag: u8) -> Self {
         let mut result = Self::new();
@@ -112,6 +195,21 @@ impl MacFrame {
         result
     }
 
+    #[inline]
+    pub fn new_block_ack(src: u8, dest: u8, tag: u8, bitmap: u16) -> Self {
+        let mut result = Self::new();
+
+        result
+            .set_src(src)
+            .set_dest(dest)
+            .set_op(Self::OP_BLOCK_ACK)
+            .set_tag(tag)
+            .set_bitmap(bitmap)
+            .generate_crc();
+
+        result
+    }
+
     #[inline]
     pub fn new_ping_request(src: u8, dest: u8, tag: u8) -> Self {
         let mut result = Self::new();
@@ -148,10 +246,10 @@ impl MacFrame {
 
     #[inline]
     pub fn get_size(&self) -> usize {
-        Self::MAC_DATA_SIZE + if self.is_data() {
-            self.inner[Self::MAC_DATA_SIZE] as usize + 1
-        } else {
-            0
+        match self.get_op() {
+            Self::OP_DATA => Self::MAC_HEADER_SIZE + self.get_payload_size(),
+            Self::OP_BLOCK_ACK => Self::BITMAP_INDEX + Self::BITMAP_SIZE,
+            _ => Self::MAC_DATA_SIZE,
         }
     }
 
@@ -172,17 +270,20 @@ impl MacFrame {
     #[inline]
     pub fn get_payload_size(&self) -> usize {
         if self.is_data() {
-            self.inner[Self::MAC_DATA_SIZE] as usize
+            slice_to_le_u16(&self.inner[Self::LENGTH_INDEX..][..Self::LENGTH_SIZE]) as usize
         } else {
             0
         }
     }
 
     #[inline]
-    pub fn get_src(&self) -> MacAddress { (self.inner[Self::MAC_INDEX] >> 0) & 0b1111 }
+    pub fn get_version(&self) -> u8 { self.inner[Self::VERSION_INDEX] }
 
     #[inline]
-    pub fn get_dest(&self) -> MacAddress { (self.inner[Self::MAC_INDEX] >> 4) & 0b1111 }
+    pub fn get_src(&self) -> MacAddress { self.inner[Self::SRC_INDEX] }
+
+    #[inline]
+    pub fn get_dest(&self) -> MacAddress { self.inner[Self::DEST_INDEX] }
 
     #[inline]
     pub fn get_op(&self) -> u8 { (self.inner[Self::OP_INDEX] >> 0) & 0b1111 }
@@ -190,11 +291,38 @@ impl MacFrame {
     #[inline]
     pub fn get_tag(&self) -> u8 { (self.inner[Self::OP_INDEX] >> 4) & 0b1111 }
 
+    #[inline]
+    pub fn get_base(&self) -> u8 { self.inner[Self::BASE_INDEX] }
+
+    #[inline]
+    pub fn get_sequence(&self) -> u8 {
+        let base = self.get_base();
+        base.wrapping_add(self.get_tag().wrapping_sub(base) & 0b1111)
+    }
+
+    #[inline]
+    pub fn get_bitmap(&self) -> u16 {
+        if self.get_op() == MacFrame::OP_BLOCK_ACK {
+            slice_to_le_u16(&self.inner[Self::BITMAP_INDEX..][..Self::BITMAP_SIZE])
+        } else if self.get_op() == MacFrame::OP_ACK {
+            1 << self.get_tag()
+        } else {
+            0
+        }
+    }
+
+    pub fn get_ack_tags(&self) -> Vec<u8> {
+        let bitmap = self.get_bitmap();
+        (0..16u8).filter(|tag| (bitmap >> tag) & 1 == 1).collect()
+    }
+
     #[inline]
     pub fn to_broadcast(&self) -> bool { self.get_dest() == MacFrame::BROADCAST_MAC }
 
     #[inline]
-    pub fn is_ack(&self) -> bool { self.get_op() == MacFrame::OP_ACK }
+    pub fn is_ack(&self) -> bool {
+        self.get_op() == MacFrame::OP_ACK || self.get_op() == MacFrame::OP_BLOCK_ACK
+    }
 
     #[inline]
     pub fn is_data(&self) -> bool { self.get_op() == MacFrame::OP_DATA }
@@ -203,19 +331,48 @@ impl MacFrame {
     pub fn is_ping_request(&self) -> bool { self.get_op() == MacFrame::OP_PING_REQ }
 
     #[inline]
-    pub fn check(&self, mac_addr: u8) -> bool {
-        let crc_flag = if self.is_data() {
+    pub fn is_ping_reply(&self) -> bool { self.get_op() == MacFrame::OP_PING_REPLY }
+
+    #[inline]
+    pub fn check_crc(&self) -> bool {
+        let complete = match Self::frame_size(&self.inner) {
+            Some(Ok(size)) => self.inner.len() >= size,
+            _ => false,
+        };
+
+        if !complete {
+            false
+        } else if self.is_data() {
             crc16_checksum(self.inner[..self.get_total_size()].iter().cloned()) == 0
         } else {
             crc8_checksum(self.inner[..self.get_total_size()].iter().cloned()) == 0
-        };
+        }
+    }
 
-        crc_flag && (self.get_dest() == mac_addr || self.get_dest() == MacFrame::BROADCAST_MAC)
+    #[inline]
+    pub fn check(&self, mac_addr: u8) -> bool {
+        self.check_crc() &&
+            (self.get_dest() == mac_addr || self.get_dest() == MacFrame::BROADCAST_MAC)
     }
 
     #[inline]
     pub fn unwrap(&self) -> Box<[u8]> {
         let size = self.get_payload_size();
-        self.inner[Self::MAC_DATA_SIZE + 1..][..size].iter().cloned().collect()
+        self.inner[Self::MAC_HEADER_SIZE..][..size].iter().cloned().collect()
+    }
+}
+
+impl std::fmt::Debug for MacFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MacFrame")
+            .field("version", &self.get_version())
+            .field("source", &self.get_src())
+            .field("destination", &self.get_dest())
+            .field("op", &self.get_op())
+            .field("tag", &self.get_tag())
+            .field("base", &if self.is_data() { Some(self.get_base()) } else { None })
+            .field("payload size", &self.get_payload_size())
+            .field("checksum", &self.check_crc())
+            .finish()
     }
 }
@@ -1,56 +1,215 @@
-mod physical;
+pub mod physical;
 mod rtaudio;
+pub mod backend;
+pub mod fec;
+pub mod scrambler;
+pub mod complex;
+pub mod ofdm;
+pub mod psk;
+pub mod pulse;
+pub mod passband;
+pub mod channel;
+pub mod loopback;
+pub mod wav;
 pub mod mac;
+pub mod rtt;
+#[cfg(test)]
+mod testing;
 
 
-use std::sync::{
-    Arc, atomic::{AtomicBool, Ordering},
-    mpsc::{self, Receiver, RecvError, Sender, SyncSender, SendError, RecvTimeoutError},
-};
+use std::{collections::VecDeque, sync::{
+    Arc, Condvar, Mutex, atomic::{AtomicBool, Ordering},
+    mpsc::{
+        self, Receiver, RecvError, Sender, SyncSender, SendError, RecvTimeoutError, TryRecvError,
+    },
+}};
 use rand::{Rng, thread_rng};
-use mac::{MacFrame, MacAddress, MAC_PAYLOAD_MAX};
-use rtaudio::{Stream, create_input_stream, create_output_stream};
-use physical::{modulate, Demodulator};
+use mac::{MacFrame, MacAddress, MacConfig, CRC_SIZE};
+use backend::SampleBackend;
+use physical::{modulate, Demodulator, FrameInfo, PhyConfig};
+use rtt::{RttEstimate, RttEstimator};
+
+pub use rtaudio::{RtAudio, Device, devices};
 
 
 const ACK_TIMEOUT: usize = 1100;
 const BACK_OFF_WINDOW: usize = 500;
 const FRAME_INTERVAL: usize = 50;
+const MAX_WINDOW: usize = 8;
+const MAX_RTO_SCALE: usize = 16;
+
 
+type Outgoing = (MacFrame, Sender<bool>);
+type SendLink = Box<dyn FnMut(&mut [i16], &Receiver<Outgoing>) -> usize + Send>;
+type ReceiveLink = Box<dyn FnMut(&[i16]) + Send>;
+type Received = (Box<[u8]>, FrameInfo);
 
 enum SendState<I> {
     Idle(usize),
     Sending(MacFrame, I, usize),
-    WaitAck(MacFrame, usize, usize),
 }
 
-pub struct Athernet {
-    sender: SyncSender<MacFrame>,
-    receiver: Receiver<MacFrame>,
+struct LinkEvents {
+    ack_send: Receiver<(u8, u8)>,
+    ack_recv: Receiver<(u8, u8, u8)>,
+    ping: Receiver<(u8, u8)>,
+}
+
+struct Peer {
+    rtt: RttEstimator,
+    in_flight: VecDeque<u8>,
+    expected: u8,
+    reorder: Vec<(MacFrame, FrameInfo)>,
+}
+
+struct Peers {
+    table: Mutex<Vec<Peer>>,
+    released: Condvar,
+}
+
+impl Peers {
+    fn new(rtt: RttEstimator) -> Self {
+        let table = (0..=MacFrame::BROADCAST_MAC).map(|_| Peer {
+            rtt, in_flight: VecDeque::new(), expected: 0, reorder: Vec::new(),
+        }).collect();
+
+        Self { table: Mutex::new(table), released: Condvar::new() }
+    }
+
+    fn acquire(&self, frame: &MacFrame, window_size: usize) {
+        if !frame.is_data() || frame.to_broadcast() { return; }
+
+        let (dest, sequence) = (frame.get_dest() as usize, frame.get_sequence());
+
+        let mut table = self.released.wait_while(self.table.lock().unwrap(), |table| {
+            table[dest].in_flight.front()
+                .is_some_and(|oldest| sequence.wrapping_sub(*oldest) as usize >= window_size)
+        }).unwrap();
+
+        table[dest].in_flight.push_back(sequence);
+    }
+
+    fn release(&self, frame: &MacFrame) {
+        if !frame.is_data() || frame.to_broadcast() { return; }
+
+        let mut table = self.table.lock().unwrap();
+        table[frame.get_dest() as usize].in_flight.retain(|item| *item != frame.get_sequence());
+        drop(table);
+
+        self.released.notify_all();
+    }
+
+    fn rebase(&self, frame: MacFrame) -> MacFrame {
+        if !frame.is_data() || frame.to_broadcast() { return frame; }
+
+        let table = self.table.lock().unwrap();
+        let base = table[frame.get_dest() as usize].in_flight.front().cloned();
+
+        frame.rebase(base.unwrap_or_else(|| frame.get_sequence()))
+    }
+
+    fn sequence(&self, frame: MacFrame, info: FrameInfo, sender: &Sender<(MacFrame, FrameInfo)>)
+                -> bool {
+        let mut table = self.table.lock().unwrap();
+        let peer = &mut table[frame.get_src() as usize];
+        let sequence = frame.get_sequence();
+        let expected = peer.expected;
+        let skip = frame.get_base().wrapping_sub(expected) as usize;
+
+        if skip > 0 && skip <= u8::MAX as usize - MAX_WINDOW {
+            peer.reorder.sort_by_key(|(item, _)| item.get_sequence().wrapping_sub(expected));
+
+            while let Some((item, _)) = peer.reorder.first() {
+                if item.get_sequence().wrapping_sub(expected) as usize >= skip { break; }
+                sender.send(peer.reorder.remove(0)).unwrap();
+            }
+
+            peer.expected = frame.get_base();
+        }
+
+        let offset = sequence.wrapping_sub(peer.expected) as usize;
+
+        if offset >= MAX_WINDOW { return offset > u8::MAX as usize - MAX_WINDOW; }
+
+        if peer.reorder.iter().any(|(item, _)| item.get_sequence() == sequence) { return true; }
+
+        peer.reorder.push((frame, info));
+
+        while let Some(index) = peer.reorder.iter()
+            .position(|(item, _)| item.get_sequence() == peer.expected) {
+            sender.send(peer.reorder.remove(index)).unwrap();
+            peer.expected = peer.expected.wrapping_add(1);
+        }
+
+        true
+    }
+}
+
+struct Outstanding {
+    frame: MacFrame,
+    timer: usize,
+    elapsed: usize,
+    count: usize,
+    delivery: Sender<bool>,
+}
+
+pub struct Delivery {
+    receiver: Receiver<bool>,
+}
+
+impl Delivery {
+    pub fn wait(&self) -> bool { self.receiver.recv().unwrap_or(false) }
+
+    pub fn try_wait(&self) -> Option<bool> {
+        match self.receiver.try_recv() {
+            Ok(delivered) => Some(delivered),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(false),
+        }
+    }
+}
+
+pub struct Athernet<B: SampleBackend> {
+    sender: SyncSender<Outgoing>,
+    receiver: Receiver<(MacFrame, FrameInfo)>,
     ping_receiver: Receiver<(u8, u8)>,
-    _input_stream: Stream,
-    _output_stream: Stream,
+    peers: Arc<Peers>,
+    window_size: usize,
+    sample_rate: u32,
+    _input_stream: B::Stream,
+    _output_stream: B::Stream,
 }
 
-impl Athernet {
-    fn create_send_stream(
+fn reply_quiet_time(config: &PhyConfig) -> usize { config.turnaround() }
+
+fn quiet_time(config: &PhyConfig) -> usize { reply_quiet_time(config) * 2 + FRAME_INTERVAL }
+
+impl<B: SampleBackend> Athernet<B> {
+    fn send_link(
         mac_addr: u8,
         guard: Arc<AtomicBool>,
-        ack_send_receiver: Receiver<(u8, u8)>,
-        ack_recv_receiver: Receiver<(u8, u8)>,
-        ping_receiver: Receiver<(u8, u8)>,
-        perf: bool,
-    ) -> Result<(SyncSender<MacFrame>, Stream), Box<dyn std::error::Error>> {
-        let (sender, receiver) = mpsc::sync_channel(0);
+        events: LinkEvents,
+        peers: Arc<Peers>,
+        config: PhyConfig,
+        mac_config: MacConfig,
+    ) -> impl FnMut(&mut [i16], &Receiver<Outgoing>) -> usize + Send + 'static {
+        let window_size = mac_config.window_size;
+        let max_retries = mac_config.max_retries;
+        let reply_quiet_time = reply_quiet_time(&config);
+        let quiet_time = quiet_time(&config);
 
         let mut send_state = SendState::Idle(0);
-        let mut buffer: Option<(MacFrame, usize, usize)> = None;
+        let mut window: Vec<Outstanding> = Vec::new();
+        let mut acks = VecDeque::new();
+        let mut replies = VecDeque::new();
+        let mut quiet = 0;
 
+        let rebase = peers.clone();
         let sending = move |frame: MacFrame, count| {
-            SendState::Sending(frame, modulate(frame), count)
+            SendState::Sending(frame.clone(), modulate(rebase.rebase(frame), &config), count)
         };
 
-        let backoff = move |frame: MacFrame, count: usize| {
+        let backoff = |frame: &MacFrame, count: usize| {
             let maximum = 1 << std::cmp::min(4, count);
             let back_off = if frame.is_data() {
                 thread_rng().gen_range::<usize, usize, usize>(0, maximum)
@@ -58,126 +217,203 @@ impl Athernet {
                 0
             };
 
-            Some((frame, back_off * BACK_OFF_WINDOW, count))
+            back_off * BACK_OFF_WINDOW
         };
 
-        let mut bit_count = 0;
-        let mut time = std::time::SystemTime::now();
+        let key = |frame: &MacFrame| (frame.get_dest(), frame.get_tag(), frame.get_op());
 
-        let stream = create_output_stream(move |data: &mut [i16]| {
+        move |data: &mut [i16], receiver: &Receiver<Outgoing>| {
             let channel_free = guard.load(Ordering::SeqCst);
+            let mut bit_count = 0;
 
-            if let Some((_, ref mut time, _)) = buffer {
-                *time = time.saturating_sub(data.len());
-            };
+            quiet = if channel_free { quiet + data.len() } else { 0 };
+
+            for tag in events.ack_send.try_iter() {
+                if !acks.contains(&tag) { acks.push_back(tag); }
+            }
+
+            replies.extend(events.ping.try_iter());
+
+            for ack in events.ack_recv.try_iter() {
+                window.retain(|item| {
+                    if key(&item.frame) != ack { return true; }
+
+                    peers.table.lock().unwrap()[ack.0 as usize].rtt
+                        .sample(item.elapsed, item.count > 1);
+
+                    bit_count += item.frame.get_payload_size() * 8;
+                    peers.release(&item.frame);
+                    let _ = item.delivery.send(true);
+                    false
+                });
+            }
 
             match send_state {
                 SendState::Idle(ref mut time) => {
                     *time = time.saturating_sub(data.len());
 
-                    if *time == 0 && channel_free {
-                        if let Some((dest, tag)) = ack_send_receiver.try_iter().next() {
-                            send_state = sending(MacFrame::new_ack(mac_addr, dest, tag), 0);
-                        } else if let Some((dest, tag)) = ping_receiver.try_iter().next() {
+                    if channel_free {
+                        for item in window.iter_mut() {
+                            item.timer = item.timer.saturating_sub(data.len());
+                            item.elapsed += data.len();
+                        }
+                    }
+
+                    window.retain(|item| {
+                        if item.timer > 0 || item.count <= max_retries { return true; }
+
+                        peers.release(&item.frame);
+                        let _ = item.delivery.send(false);
+                        false
+                    });
+
+                    if *time == 0 && quiet >= reply_quiet_time {
+                        if let Some((dest, tag)) = acks.pop_front() {
+                            let mut bitmap = 1u16 << tag;
+
+                            acks.retain(|(item, tag)| {
+                                if *item == dest { bitmap |= 1 << tag; }
+                                *item != dest
+                            });
+
+                            send_state = sending(if bitmap.count_ones() > 1 {
+                                MacFrame::new_block_ack(mac_addr, dest, tag, bitmap)
+                            } else {
+                                MacFrame::new_ack(mac_addr, dest, tag)
+                            }, 0);
+                        } else if let Some((dest, tag)) = replies.pop_front() {
                             send_state = sending(MacFrame::new_ping_reply(mac_addr, dest, tag), 0);
-                        } else if let Some((frame, time, count)) = buffer {
-                            if time == 0 {
-                                send_state = sending(frame, count + 1);
-                                buffer = None;
-                            };
-                        } else if let Some(frame) = receiver.try_iter().next() {
-                            send_state = sending(frame, 0);
-                        };
-                    } else {
-                        for _ in ack_send_receiver.try_iter() {}
+                        } else if quiet >= quiet_time {
+                            if let Some(index) = window.iter().position(|item| item.timer == 0) {
+                                let item = &mut window[index];
+                                let mut table = peers.table.lock().unwrap();
+                                let estimator = &mut table[item.frame.get_dest() as usize].rtt;
+
+                                if index == 0 { estimator.backoff(); }
+                                let rto = estimator.rto();
+                                drop(table);
+
+                                item.timer = rto + backoff(&item.frame, item.count);
+                                item.elapsed = 0;
+                                send_state = sending(item.frame.clone(), item.count);
+                            } else if window.len() < window_size {
+                                if let Some((frame, delivery)) = receiver.try_iter().next() {
+                                    if frame.is_data() || frame.is_ping_request() {
+                                        let dest = frame.get_dest() as usize;
+                                        let timer = peers.table.lock().unwrap()[dest].rtt.rto();
+
+                                        window.push(Outstanding {
+                                            frame: frame.clone(), timer, elapsed: 0, count: 0,
+                                            delivery,
+                                        });
+                                    } else {
+                                        let _ = delivery.send(true);
+                                    }
+                                    send_state = sending(frame, 0);
+                                }
+                            }
+                        }
                     }
                 }
-                SendState::Sending(frame, ref mut iter, count) => {
+                SendState::Sending(ref frame, ref mut iter, count) => {
                     if channel_free {
                         for sample in data.iter_mut() {
                             if let Some(item) = iter.next() {
                                 *sample = item;
                             } else {
-                                send_state = if (frame.is_data() || frame.is_ping_request())
-                                    && !frame.to_broadcast() {
-                                    SendState::WaitAck(frame, ACK_TIMEOUT, count)
-                                } else {
-                                    SendState::Idle(0)
-                                };
+                                if let Some(item) = window.iter_mut()
+                                    .find(|item| key(&item.frame) == key(frame)) {
+                                    item.count += 1;
+                                }
+
+                                if frame.to_broadcast() {
+                                    window.retain(|item| {
+                                        if key(&item.frame) != key(frame) { return true; }
+
+                                        let _ = item.delivery.send(true);
+                                        false
+                                    });
+                                }
+
+                                send_state = SendState::Idle(
+                                    if frame.is_data() { FRAME_INTERVAL } else { 0 }
+                                );
                                 break;
                             };
                         };
                     } else {
-                        if frame.is_data() || frame.is_ping_request() {
-                            buffer = backoff(frame, count);
-                        } else if !frame.is_ack() {
-                            buffer = Some((frame, 0, count));
+                        if frame.is_ack() {
+                            for tag in frame.get_ack_tags().into_iter().rev() {
+                                acks.push_front((frame.get_dest(), tag));
+                            }
+                        } else if frame.is_ping_reply() {
+                            replies.push_front((frame.get_dest(), frame.get_tag()));
+                        } else if let Some(item) = window.iter_mut()
+                            .find(|item| key(&item.frame) == key(frame)) {
+                            item.timer = backoff(frame, count);
                         }
                         send_state = SendState::Idle(0);
                     };
                 }
-                SendState::WaitAck(frame, ref mut time, count) => {
-                    *time = time.saturating_sub(data.len());
-
-                    if *time > 0 {
-                        if ack_recv_receiver.try_iter().any(|item| {
-                            item == (frame.get_dest(), frame.get_tag())
-                        }) {
-                            bit_count += frame.get_payload_size() * 8;
-
-                            send_state = SendState::Idle(FRAME_INTERVAL);
-                        };
-                    } else {
-                        buffer = backoff(frame, count);
-                        send_state = SendState::Idle(0);
-                    };
-                }
             }
 
-            if perf && time.elapsed().unwrap() > std::time::Duration::from_secs(1) {
-                time = std::time::SystemTime::now();
-                println!("speed {} b/s", bit_count);
-                bit_count = 0;
-            }
-        })?;
-
-        Ok((sender, stream))
+            bit_count
+        }
     }
 
-    fn create_receive_stream(
+    fn create_link(
         mac_addr: u8,
-        guard: Arc<AtomicBool>,
-        ack_send_sender: Sender<(u8, u8)>,
-        ack_recv_sender: Sender<(u8, u8)>,
-        ping_sender: Sender<(u8, u8)>,
-    ) -> Result<(Receiver<MacFrame>, Receiver<(u8, u8)>, Stream), Box<dyn std::error::Error>>
-    {
-        let mut demodulator = Demodulator::new(mac_addr);
+        sender: Sender<(MacFrame, FrameInfo)>,
+        ping_send: Sender<(u8, u8)>,
+        peers: Arc<Peers>,
+        perf: bool,
+        config: PhyConfig,
+        mac_config: MacConfig,
+    ) -> (ReceiveLink, SendLink) {
+        let guard = Arc::new(AtomicBool::new(true));
+        let mut demodulator = Demodulator::new(mac_addr, config.clone());
 
-        let (sender, receiver) = mpsc::channel();
-        let (ping_send, ping_recv) = mpsc::channel();
+        let (ack_send_sender, ack_send_receiver) = mpsc::channel();
+        let (ack_recv_sender, ack_recv_receiver) = mpsc::channel();
+        let (ping_sender, ping_receiver) = mpsc::channel();
 
         let mut channel_active = false;
+        let mut time = std::time::SystemTime::now();
+
+        let events = LinkEvents {
+            ack_send: ack_send_receiver, ack_recv: ack_recv_receiver, ping: ping_receiver,
+        };
+
+        let send_link = Self::send_link(
+            mac_addr, guard.clone(), events, peers.clone(), config, mac_config,
+        );
 
-        let stream = create_input_stream(move |data: &mut [i16]| {
+        let receive_link = move |data: &[i16]| {
             for sample in data.iter() {
-                if let Some(frame) = demodulator.push_back(*sample) {
+                if let Some((frame, info)) = demodulator.push_back(*sample) {
                     if frame.check(mac_addr) {
                         let tag = (frame.get_src(), frame.get_tag());
 
                         match frame.get_op() {
-                            MacFrame::OP_ACK => {
-                                ack_recv_sender.send(tag).unwrap();
+                            MacFrame::OP_ACK | MacFrame::OP_BLOCK_ACK => {
+                                for tag in frame.get_ack_tags() {
+                                    ack_recv_sender
+                                        .send((frame.get_src(), tag, MacFrame::OP_DATA)).unwrap();
+                                }
+                            }
+                            MacFrame::OP_DATA if frame.to_broadcast() => {
+                                sender.send((frame, info)).unwrap();
                             }
                             MacFrame::OP_DATA => {
-                                ack_send_sender.send(tag).unwrap();
-                                sender.send(frame).unwrap();
+                                let accepted = peers.sequence(frame, info, &sender);
+                                if accepted { ack_send_sender.send(tag).unwrap(); }
                             }
                             MacFrame::OP_PING_REQ => {
                                 ping_sender.send(tag).unwrap();
                             }
                             MacFrame::OP_PING_REPLY => {
-                                ack_recv_sender.send(tag).unwrap();
+                                ack_recv_sender
+                                    .send((tag.0, tag.1, MacFrame::OP_PING_REQ)).unwrap();
                                 ping_send.send(tag).unwrap();
                             }
                             _ => {}
@@ -190,61 +426,192 @@ impl Athernet {
                 channel_active = demodulator.is_active();
                 guard.store(!channel_active, Ordering::SeqCst);
             }
-        })?;
 
-        Ok((receiver, ping_recv, stream))
+            if perf && time.elapsed().unwrap() > std::time::Duration::from_secs(1) {
+                time = std::time::SystemTime::now();
+                println!("thresholds: {}", demodulator.thresholds());
+            }
+        };
+
+        (Box::new(receive_link), Box::new(send_link))
     }
 
-    pub fn new(mac_addr: u8, perf: bool) -> Result<Self, Box<dyn std::error::Error>> {
-        let channel_free = Arc::new(AtomicBool::new(true));
-        let (ack_send_send, ack_send_recv) = mpsc::channel::<(u8, u8)>();
-        let (ack_recv_send, ack_recv_recv) = mpsc::channel::<(u8, u8)>();
-        let (ping_send, ping_recv) = mpsc::channel::<(u8, u8)>();
+    fn create_send_stream(
+        backend: &B, mut links: Vec<SendLink>, perf: bool, sample_rate: u32,
+    ) -> Result<(SyncSender<Outgoing>, B::Stream), Box<dyn std::error::Error>> {
+        let (sender, receiver) = mpsc::sync_channel(0);
+
+        let channels = links.len();
+        let mut buffer = Vec::new();
+
+        let mut bit_count = 0;
+        let mut time = std::time::SystemTime::now();
+
+        let stream = backend.create_output_stream(
+            sample_rate, channels as u32, move |data: &mut [i16]| {
+                for (channel, link) in links.iter_mut().enumerate() {
+                    buffer.clear();
+                    buffer.extend(data.iter().skip(channel).step_by(channels));
+
+                    bit_count += link(&mut buffer, &receiver);
 
-        let (receiver, ping_receiver, _output_stream)
-            = Self::create_receive_stream(
-            mac_addr, channel_free.clone(), ack_send_send, ack_recv_send, ping_send,
+                    for (sample, item) in data.iter_mut().skip(channel).step_by(channels)
+                        .zip(buffer.iter()) {
+                        *sample = *item;
+                    }
+                }
+
+                if perf && time.elapsed().unwrap() > std::time::Duration::from_secs(1) {
+                    time = std::time::SystemTime::now();
+                    println!("speed {} b/s", bit_count);
+                    bit_count = 0;
+                }
+            },
         )?;
-        let (sender, _input_stream) = Self::create_send_stream(
-            mac_addr, channel_free.clone(), ack_send_recv, ack_recv_recv, ping_recv, perf,
+
+        Ok((sender, stream))
+    }
+
+    fn create_receive_stream(
+        backend: &B, mut links: Vec<ReceiveLink>, sample_rate: u32,
+    ) -> Result<B::Stream, Box<dyn std::error::Error>> {
+        let channels = links.len();
+        let mut buffer = Vec::new();
+
+        Ok(backend.create_input_stream(sample_rate, channels as u32, move |data: &mut [i16]| {
+            for (channel, link) in links.iter_mut().enumerate() {
+                buffer.clear();
+                buffer.extend(data.iter().skip(channel).step_by(channels));
+
+                link(&buffer);
+            }
+        })?)
+    }
+
+    pub fn new(
+        backend: &B, mac_addr: u8, perf: bool, config: PhyConfig, mac_config: MacConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        config.validate()?;
+
+        let (sender, receiver) = mpsc::channel();
+        let (ping_send, ping_receiver) = mpsc::channel();
+
+        let ack_timeout = ACK_TIMEOUT + quiet_time(&config);
+        let peers = Arc::new(Peers::new(
+            RttEstimator::new(ack_timeout, quiet_time(&config), ack_timeout * MAX_RTO_SCALE),
+        ));
+
+        let window_size = mac_config.window_size.clamp(1, MAX_WINDOW);
+        let mac_config = MacConfig { window_size, ..mac_config };
+
+        let mut send_links = Vec::new();
+        let mut receive_links = Vec::new();
+
+        for _ in 0..std::cmp::max(config.channels, 1) {
+            let (receive_link, send_link) = Self::create_link(
+                mac_addr, sender.clone(), ping_send.clone(), peers.clone(), perf, config.clone(),
+                mac_config.clone(),
+            );
+
+            receive_links.push(receive_link);
+            send_links.push(send_link);
+        }
+
+        let sample_rate = config.sample_rate;
+
+        let _input_stream = Self::create_receive_stream(backend, receive_links, sample_rate)?;
+        let (sender, _output_stream) = Self::create_send_stream(
+            backend, send_links, perf, sample_rate,
         )?;
 
-        Ok(Self { sender, receiver, ping_receiver, _input_stream, _output_stream })
+        Ok(Self {
+            sender, receiver, ping_receiver, peers, window_size, sample_rate,
+            _input_stream, _output_stream,
+        })
     }
 
-    pub fn send(&self, data: MacFrame) -> Result<(), SendError<MacFrame>> {
-        self.sender.send(data)
+    pub fn send(&self, data: MacFrame) -> Result<Delivery, SendError<MacFrame>> {
+        let (sender, receiver) = mpsc::channel();
+
+        self.peers.acquire(&data, self.window_size);
+
+        self.sender.send((data, sender)).map_err(|SendError((frame, _))| SendError(frame))?;
+
+        Ok(Delivery { receiver })
     }
 
-    pub fn recv(&self) -> Result<MacFrame, RecvError> { self.receiver.recv() }
+    pub fn recv(&self) -> Result<(MacFrame, FrameInfo), RecvError> { self.receiver.recv() }
 
     pub fn ping_recv_timeout(&self, timeout: std::time::Duration)
                              -> Result<(u8, u8), RecvTimeoutError>
     {
         self.ping_receiver.recv_timeout(timeout)
     }
+
+    pub fn get_rtt(&self, dest: u8) -> Option<RttEstimate> {
+        self.peers.table.lock().unwrap()[dest as usize].rtt.estimate(self.sample_rate)
+    }
 }
 
-pub struct MacLayer {
-    athernet: Athernet,
-    send_tag: [u8; 255],
-    recv_tag: [u8; 255],
+pub struct MacLayer<B: SampleBackend = RtAudio> {
+    athernet: Athernet<B>,
+    send_tag: [u8; 256],
+    ping_tag: [u8; 256],
+    pending: Vec<(MacFrame, FrameInfo)>,
     mac_addr: u8,
+    mtu: usize,
 }
 
-impl MacLayer {
-    pub fn get_mtu(&self) -> usize { MAC_PAYLOAD_MAX - 1 }
+impl MacLayer<RtAudio> {
+    pub fn new(
+        mac_addr: MacAddress, perf: bool, config: PhyConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_backend(&RtAudio::default(), mac_addr, perf, config)
+    }
+}
+
+impl<B: SampleBackend> MacLayer<B> {
+    pub fn get_mtu(&self) -> usize { self.mtu }
+
+    pub fn get_rtt(&self, dest: MacAddress) -> Option<RttEstimate> { self.athernet.get_rtt(dest) }
+
+    pub fn with_backend(
+        backend: &B, mac_addr: MacAddress, perf: bool, config: PhyConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_config(backend, mac_addr, perf, config, MacConfig::default())
+    }
+
+    pub fn with_config(
+        backend: &B, mac_addr: MacAddress, perf: bool, config: PhyConfig, mac_config: MacConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let overhead = MacFrame::MAC_HEADER_SIZE + CRC_SIZE;
+
+        if config.payload_max() <= overhead {
+            Err(format!(
+                "payload size {} leaves no room after {} bytes of header and crc",
+                config.payload_max(), overhead,
+            ))?;
+        }
+
+        let mtu = config.payload_max() - overhead;
 
-    pub fn new(mac_addr: MacAddress, perf: bool) -> Result<Self, Box<dyn std::error::Error>> {
         Ok(Self {
-            athernet: Athernet::new(mac_addr, perf)?,
-            send_tag: [0; 255],
-            recv_tag: [0; 255],
+            athernet: Athernet::new(backend, mac_addr, perf, config, mac_config)?,
+            send_tag: [0; 256],
+            ping_tag: [0; 256],
+            pending: Vec::new(),
             mac_addr,
+            mtu,
         })
     }
 
-    pub fn send(&mut self, data: &[u8], dest: MacAddress) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn send(&mut self, data: &[u8], dest: MacAddress)
+                -> Result<Delivery, Box<dyn std::error::Error>>
+    {
+        if data.len() > self.mtu {
+            Err(format!("payload size {} exceeds mtu {}", data.len(), self.mtu))?;
+        }
+
         let send_tag = &mut self.send_tag[dest as usize];
 
         let tag = if dest == MacFrame::BROADCAST_MAC {
@@ -259,37 +626,44 @@ impl MacLayer {
     }
 
     pub fn recv(&mut self, dest: MacAddress) -> Result<Box<[u8]>, Box<dyn std::error::Error>> {
-        loop {
-            let mac_data = self.athernet.recv()?;
-            let src = mac_data.get_src();
-            let tag = mac_data.get_tag();
-            let recv_tag = &mut self.recv_tag[src as usize];
-
-            if (src, tag) == (dest, *recv_tag & 0b1111) {
-                *recv_tag = recv_tag.wrapping_add(1);
-                return Ok(mac_data.unwrap());
-            }
-        }
+        Ok(self.recv_with_info(dest)?.0)
+    }
+
+    pub fn recv_with_info(&mut self, dest: MacAddress)
+                          -> Result<Received, Box<dyn std::error::Error>>
+    {
+        let (mac_data, info) = match self.pending.iter()
+            .position(|(frame, _)| frame.get_src() == dest) {
+            Some(index) => self.pending.remove(index),
+            None => loop {
+                let (mac_data, info) = self.athernet.recv()?;
+
+                if mac_data.get_src() == dest { break (mac_data, info); }
+
+                self.pending.push((mac_data, info));
+            },
+        };
+
+        Ok((mac_data.unwrap(), info))
     }
 
     pub fn ping(&mut self, dest: MacAddress)
                 -> Result<Option<std::time::Duration>, Box<dyn std::error::Error>>
     {
-        let send_tag = &mut self.send_tag[dest as usize];
+        let ping_tag = &mut self.ping_tag[dest as usize];
+        let tag = *ping_tag & 0b1111;
+        *ping_tag = ping_tag.wrapping_add(1);
 
         let time_out = std::time::Duration::from_secs(2);
 
         let start = std::time::SystemTime::now();
 
-        self.athernet.send(MacFrame::new_ping_request(self.mac_addr, dest, *send_tag))?;
+        self.athernet.send(MacFrame::new_ping_request(self.mac_addr, dest, tag))?;
 
         loop {
             match self.athernet.ping_recv_timeout(time_out - start.elapsed()?) {
                 Ok(pair) => {
-                    if pair == (dest, *send_tag & 0b1111) {
-                        *send_tag = send_tag.wrapping_add(1);
-                        return Ok(Some(start.elapsed()?));
-                    }
+                    if pair == (dest, tag) { return Ok(Some(start.elapsed()?)); }
                 }
                 Err(std::sync::mpsc::RecvTimeoutError::Timeout) => return Ok(None),
                 Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => panic!(),
@@ -297,3 +671,184 @@ impl MacLayer {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loopback::Loopback;
+
+    const CHUNK: usize = 64;
+    const RTO: usize = 20000;
+
+    struct Node {
+        receive: ReceiveLink,
+        send: SendLink,
+        outgoing: SyncSender<Outgoing>,
+        queue: Receiver<Outgoing>,
+        monitor: Demodulator,
+    }
+
+    impl Node {
+        fn new(config: PhyConfig, mac_config: MacConfig) -> Self {
+            let (sender, _) = mpsc::channel();
+            let (ping_send, _) = mpsc::channel();
+            let peers = Arc::new(Peers::new(RttEstimator::new(RTO, RTO, 4 * RTO)));
+            let (receive, send) = Athernet::<Loopback>::create_link(
+                1, sender, ping_send, peers, false, config.clone(), mac_config,
+            );
+            let (outgoing, queue) = mpsc::sync_channel(16);
+
+            Self { receive, send, outgoing, queue, monitor: Demodulator::new(2, config) }
+        }
+
+        fn send(&self, frame: MacFrame) -> Delivery {
+            let (sender, receiver) = mpsc::channel();
+            self.outgoing.send((frame, sender)).unwrap();
+            Delivery { receiver }
+        }
+
+        fn run(&mut self, samples: usize) -> Vec<MacFrame> {
+            let mut buffer = [0; CHUNK];
+            let mut sent = Vec::new();
+
+            for _ in 0..samples / CHUNK {
+                for sample in buffer.iter_mut() { *sample = 0; }
+                (self.send)(&mut buffer, &self.queue);
+
+                sent.extend(buffer.iter()
+                    .filter_map(|sample| self.monitor.push_back(*sample))
+                    .map(|(frame, _)| frame));
+            }
+
+            sent
+        }
+
+        fn hear(&mut self, frame: MacFrame, config: &PhyConfig) {
+            let mut samples = vec![0; CHUNK];
+            samples.extend(modulate(frame, config));
+            samples.resize(samples.len() + CHUNK, 0);
+
+            (self.receive)(&samples);
+        }
+
+        fn hear_part(&mut self, samples: &[i16]) { (self.receive)(samples); }
+    }
+
+    #[test]
+    fn sequence_skips_abandoned_frames() {
+        let peers = Peers::new(RttEstimator::new(RTO, RTO, 4 * RTO));
+        let (sender, receiver) = mpsc::channel();
+        let info = FrameInfo { peak: 0, snr: 0., margin: 0., timestamp: 0 };
+        let data = |sequence, base| MacFrame::new_data(1, 2, sequence, &[sequence]).rebase(base);
+
+        assert!(peers.sequence(data(1, 1), info, &sender));
+        assert!(peers.sequence(data(1, 1), info, &sender));
+        assert!(peers.sequence(data(3, 2), info, &sender));
+        assert!(!peers.sequence(data(12, 2), info, &sender));
+        assert!(peers.sequence(data(4, 4), info, &sender));
+
+        let delivered = receiver.try_iter().map(|(frame, _)| frame.get_sequence());
+        assert_eq!(delivered.collect::<Vec<_>>(), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn addresses_above_fifteen() {
+        let config = PhyConfig::default();
+        let peers = Peers::new(RttEstimator::new(RTO, RTO, 4 * RTO));
+        let (sender, receiver) = mpsc::channel();
+        let in_flight = |dest: MacAddress| {
+            peers.table.lock().unwrap()[dest as usize].in_flight.len()
+        };
+
+        for (src, dest, listener) in [(200, 254, 254), (254, 200, 200), (200, 255, 254)].iter() {
+            let mut demodulator = Demodulator::new(*listener, config.clone());
+            let mut samples = vec![0; CHUNK];
+            samples.extend(modulate(MacFrame::new_data(*src, *dest, 0, &[*src; 40]), &config));
+            samples.resize(samples.len() + CHUNK, 0);
+
+            let (frame, info) = samples.into_iter()
+                .find_map(|sample| demodulator.push_back(sample)).unwrap();
+
+            assert_eq!((frame.get_src(), frame.get_dest()), (*src, *dest));
+            assert_eq!(frame.to_broadcast(), *dest == MacFrame::BROADCAST_MAC);
+            assert!(frame.check(*listener));
+            assert_eq!(frame.check(*dest & 0b1111), frame.to_broadcast());
+            assert_eq!(frame.unwrap()[..], [*src; 40][..]);
+
+            peers.acquire(&frame, 4);
+            assert_eq!(in_flight(*dest), if frame.to_broadcast() { 0 } else { 1 });
+            assert_eq!(in_flight(*dest & 0b1111), 0);
+            peers.release(&frame);
+            assert_eq!(in_flight(*dest), 0);
+
+            if !frame.to_broadcast() { assert!(peers.sequence(frame, info, &sender)); }
+        }
+
+        let delivered = receiver.try_iter().map(|(frame, _)| frame.get_src());
+        assert_eq!(delivered.collect::<Vec<_>>(), vec![200, 254]);
+
+        let expected = |src: MacAddress| peers.table.lock().unwrap()[src as usize].expected;
+        assert_eq!((expected(200), expected(254), expected(200 & 0b1111)), (1, 1, 0));
+    }
+
+    #[test]
+    fn block_ack_releases_bitmap() {
+        let config = PhyConfig::default();
+        let mut node = Node::new(config.clone(), MacConfig::default());
+
+        let deliveries = (0..4u8)
+            .map(|tag| node.send(MacFrame::new_data(1, 2, tag, &[tag; 40])))
+            .collect::<Vec<_>>();
+
+        let sent = node.run(RTO / 2);
+        assert_eq!(sent.iter().map(MacFrame::get_tag).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+
+        node.hear(MacFrame::new_block_ack(2, 1, 0, 0b1101), &config);
+        node.run(CHUNK);
+
+        let states = deliveries.iter().map(Delivery::try_wait).collect::<Vec<_>>();
+        assert_eq!(states, vec![Some(true), None, Some(true), Some(true)]);
+
+        let mut resent = Vec::new();
+
+        for _ in 0..4 * RTO / CHUNK {
+            resent.extend(node.run(CHUNK));
+            if !resent.is_empty() { break; }
+        }
+
+        assert_eq!(resent.iter().map(MacFrame::get_tag).collect::<Vec<_>>(), vec![1]);
+
+        node.hear(MacFrame::new_ack(2, 1, 1), &config);
+        node.run(CHUNK);
+        assert_eq!(deliveries[1].try_wait(), Some(true));
+
+        assert!(node.run(4 * RTO).is_empty());
+    }
+
+    #[test]
+    fn interrupted_last_attempt_kept() {
+        let config = PhyConfig::default();
+        let mac_config = MacConfig { max_retries: 0, ..MacConfig::default() };
+        let mut node = Node::new(config.clone(), mac_config);
+        let delivery = node.send(MacFrame::new_data(1, 2, 0, &[0; 40]));
+
+        node.run(10 * CHUNK);
+
+        let mut busy = modulate(MacFrame::new_data(3, 4, 0, &[0; 100]), &config)
+            .collect::<Vec<_>>();
+        busy.resize(busy.len() + CHUNK, 0);
+        let (head, tail) = busy.split_at(600);
+
+        node.hear_part(head);
+        node.run(CHUNK);
+        node.hear_part(tail);
+
+        node.run(RTO / 2);
+        assert_eq!(delivery.try_wait(), None);
+
+        node.hear(MacFrame::new_ack(2, 1, 0), &config);
+        node.run(CHUNK);
+        assert_eq!(delivery.try_wait(), Some(true));
+    }
+}
@@ -0,0 +1,224 @@
+use crate::athernet::complex::Complex;
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct OfdmConfig {
+    pub fft_len: usize,
+    pub cyclic_prefix: usize,
+    pub first_carrier: usize,
+    pub carrier_count: usize,
+    pub pilot_interval: usize,
+}
+
+impl Default for OfdmConfig {
+    fn default() -> Self {
+        Self {
+            fft_len: 64,
+            cyclic_prefix: 16,
+            first_carrier: 2,
+            carrier_count: 24,
+            pilot_interval: 4,
+        }
+    }
+}
+
+impl OfdmConfig {
+    const RMS: f32 = 8000.;
+
+    #[inline]
+    pub fn symbol_len(&self) -> usize { self.fft_len + self.cyclic_prefix }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.fft_len.is_power_of_two() {
+            return Err(format!("fft length {} is not a power of two", self.fft_len));
+        }
+
+        if self.cyclic_prefix > self.fft_len {
+            return Err(format!(
+                "cyclic prefix {} is longer than the fft {}", self.cyclic_prefix, self.fft_len,
+            ));
+        }
+
+        if self.pilot_interval == 0 { return Err("pilot interval must be positive".into()); }
+
+        if self.first_carrier == 0 { return Err("the dc bin cannot carry data".into()); }
+
+        if self.first_carrier + self.carrier_count >= self.fft_len / 2 {
+            return Err(format!(
+                "carriers {}..{} run past half of the {} point fft",
+                self.first_carrier, self.first_carrier + self.carrier_count, self.fft_len,
+            ));
+        }
+
+        if self.bits_per_symbol() == 0 { return Err("every carrier is a pilot".into()); }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn is_pilot(&self, index: usize) -> bool {
+        index.is_multiple_of(self.pilot_interval) || index + 1 == self.carrier_count
+    }
+
+    #[inline]
+    pub fn bits_per_symbol(&self) -> usize {
+        (0..self.carrier_count).filter(|index| !self.is_pilot(*index)).count() * 2
+    }
+
+    #[inline]
+    fn scale(&self) -> f32 { Self::RMS / (self.carrier_count as f32 / 2.).sqrt() }
+}
+
+
+pub fn fft(buffer: &mut [Complex], inverse: bool) {
+    let len = buffer.len();
+    let bits = len.trailing_zeros();
+
+    for index in 0..len {
+        let reverse = index.reverse_bits() >> (std::mem::size_of::<usize>() as u32 * 8 - bits);
+        if index < reverse { buffer.swap(index, reverse); }
+    }
+
+    let sign = if inverse { 1. } else { -1. };
+    let mut size = 2;
+
+    while size <= len {
+        let step = Complex::from_polar(1., sign * 2. * std::f32::consts::PI / size as f32);
+
+        for start in (0..len).step_by(size) {
+            let mut twiddle = Complex::new(1., 0.);
+
+            for index in start..start + size / 2 {
+                let odd = buffer[index + size / 2] * twiddle;
+                buffer[index + size / 2] = buffer[index] - odd;
+                buffer[index] = buffer[index] + odd;
+                twiddle = twiddle * step;
+            }
+        }
+
+        size *= 2;
+    }
+}
+
+
+pub struct OfdmModulate<I> {
+    config: OfdmConfig,
+    iter: I,
+    buffer: Vec<i16>,
+    index: usize,
+}
+
+impl<I: Iterator<Item=bool>> OfdmModulate<I> {
+    pub fn new(config: OfdmConfig, iter: I) -> Self {
+        Self { config, iter, buffer: Vec::new(), index: 0 }
+    }
+
+    fn next_symbol(&mut self) -> bool {
+        let config = self.config;
+        let bits = (0..config.bits_per_symbol()).map(|_| self.iter.next()).collect::<Vec<_>>();
+
+        if bits[0].is_none() { return false; }
+
+        let mut bits = bits.into_iter().map(|bit| bit.unwrap_or(false));
+        let mut spectrum = vec![Complex::default(); config.fft_len];
+        let amplitude = std::f32::consts::FRAC_1_SQRT_2;
+
+        for index in 0..config.carrier_count {
+            let carrier = config.first_carrier + index;
+
+            spectrum[carrier] = if config.is_pilot(index) {
+                Complex::new(1., 0.)
+            } else {
+                let re = if bits.next().unwrap() { amplitude } else { -amplitude };
+                let im = if bits.next().unwrap() { amplitude } else { -amplitude };
+                Complex::new(re, im)
+            };
+
+            spectrum[config.fft_len - carrier] = spectrum[carrier].conj();
+        }
+
+        fft(&mut spectrum, true);
+
+        let scale = config.scale();
+        let samples = spectrum.iter().map(|item| {
+            (item.re * scale).max(i16::MIN as f32).min(i16::MAX as f32) as i16
+        }).collect::<Vec<_>>();
+
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&samples[config.fft_len - config.cyclic_prefix..]);
+        self.buffer.extend_from_slice(&samples);
+        self.index = 0;
+
+        true
+    }
+}
+
+impl<I: Iterator<Item=bool>> Iterator for OfdmModulate<I> {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == self.buffer.len() && !self.next_symbol() { return None; }
+
+        let index = self.index;
+        self.index += 1;
+        Some(self.buffer[index])
+    }
+}
+
+
+fn pilot_delay(config: &OfdmConfig, carriers: &[Complex]) -> f32 {
+    let interval = config.pilot_interval;
+
+    let slope = (0..config.carrier_count).step_by(interval)
+        .filter(|index| index + interval < config.carrier_count)
+        .fold(Complex::default(), |sum, index| {
+            sum + carriers[index + interval] * carriers[index].conj()
+        });
+
+    slope.arg() * config.fft_len as f32 / (2. * std::f32::consts::PI * interval as f32)
+}
+
+pub fn demodulate<I: Iterator<Item=i16>>(config: &OfdmConfig, symbol: I) -> (Vec<f32>, f32) {
+    let start = config.cyclic_prefix / 2;
+    let early = (config.cyclic_prefix - start) as f32;
+
+    let mut spectrum = symbol.skip(start).take(config.fft_len)
+        .map(|item| Complex::new(item as f32, 0.))
+        .collect::<Vec<_>>();
+
+    spectrum.resize(config.fft_len, Complex::default());
+
+    fft(&mut spectrum, false);
+
+    let step = 2. * std::f32::consts::PI * early / config.fft_len as f32;
+
+    let carriers = spectrum[config.first_carrier..][..config.carrier_count].iter()
+        .enumerate()
+        .map(|(index, item)| {
+            *item * Complex::from_polar(1., step * (config.first_carrier + index) as f32)
+        })
+        .collect::<Vec<_>>();
+    let pilots = (0..config.carrier_count).filter(|index| config.is_pilot(*index))
+        .collect::<Vec<_>>();
+
+    let mut result = Vec::with_capacity(config.bits_per_symbol());
+
+    for index in (0..config.carrier_count).filter(|index| !config.is_pilot(*index)) {
+        let after = pilots.iter().position(|pilot| *pilot > index).unwrap_or(pilots.len() - 1);
+        let (left, right) = (pilots[after.saturating_sub(1)], pilots[after]);
+
+        let response = if left == right {
+            carriers[left]
+        } else {
+            let ratio = (index - left) as f32 / (right - left) as f32;
+            carriers[left] + (carriers[right] - carriers[left]).scale(ratio)
+        };
+
+        let value = carriers[index] / response;
+
+        result.push(value.re);
+        result.push(value.im);
+    }
+
+    (result, pilot_delay(config, &carriers))
+}
@@ -0,0 +1,149 @@
+use std::{collections::VecDeque, f32::consts::PI};
+use crate::athernet::complex::Complex;
+
+
+const LOWPASS_TAPS: usize = 33;
+
+struct Oscillator {
+    phase: f32,
+    step: f32,
+}
+
+impl Oscillator {
+    fn new(frequency: f32, sample_rate: u32) -> Self {
+        Self { phase: 0., step: 2. * PI * frequency / sample_rate as f32 }
+    }
+
+    #[inline]
+    fn next(&mut self) -> Complex {
+        let value = Complex::from_polar(1., self.phase);
+        self.phase = (self.phase + self.step) % (2. * PI);
+        value
+    }
+}
+
+pub fn upconvert<I>(iter: I, frequency: f32, sample_rate: u32) -> impl Iterator<Item=i16>
+    where I: Iterator<Item=i16>
+{
+    let mut oscillator = Oscillator::new(frequency, sample_rate);
+
+    iter.map(move |sample| (sample as f32 * oscillator.next().re) as i16)
+}
+
+
+pub struct Downconverter {
+    oscillator: Oscillator,
+    taps: Box<[f32]>,
+    buffer: VecDeque<Complex>,
+}
+
+impl Downconverter {
+    pub fn new(frequency: f32, sample_rate: u32) -> Self {
+        let cutoff = frequency.min(sample_rate as f32 / 2. - frequency) / sample_rate as f32;
+        let center = (LOWPASS_TAPS - 1) as f32 / 2.;
+
+        let taps = (0..LOWPASS_TAPS).map(|index| {
+            let t = index as f32 - center;
+            let sinc = if t.abs() < 1e-6 {
+                2. * cutoff
+            } else {
+                (2. * PI * cutoff * t).sin() / (PI * t)
+            };
+            let window = 0.54 - 0.46 * (2. * PI * index as f32 / (LOWPASS_TAPS - 1) as f32).cos();
+
+            sinc * window
+        }).collect::<Vec<_>>();
+
+        let gain = 2. / taps.iter().sum::<f32>();
+
+        Self {
+            oscillator: Oscillator::new(frequency, sample_rate),
+            taps: taps.iter().map(|tap| tap * gain).collect(),
+            buffer: VecDeque::from(vec![Complex::default(); LOWPASS_TAPS]),
+        }
+    }
+
+    pub fn push(&mut self, item: i16) -> Complex {
+        let mixed = self.oscillator.next().conj().scale(item as f32);
+
+        self.buffer.pop_front();
+        self.buffer.push_back(mixed);
+
+        self.buffer.iter().zip(self.taps.iter())
+            .fold(Complex::default(), |sum, (sample, tap)| sum + sample.scale(*tap))
+    }
+}
+
+
+pub const BANDPASS_TAPS: usize = 255;
+
+pub struct Bandpass {
+    taps: Box<[f32]>,
+    buffer: VecDeque<f32>,
+}
+
+impl Bandpass {
+    pub fn new(low: f32, high: f32, sample_rate: u32) -> Self {
+        let (low, high) = (low / sample_rate as f32, high / sample_rate as f32);
+        let center = (BANDPASS_TAPS - 1) as f32 / 2.;
+
+        let taps = (0..BANDPASS_TAPS).map(|index| {
+            let t = index as f32 - center;
+            let ideal = if t.abs() < 1e-6 {
+                2. * (high - low)
+            } else {
+                ((2. * PI * high * t).sin() - (2. * PI * low * t).sin()) / (PI * t)
+            };
+            let window = 0.54 - 0.46 * (2. * PI * index as f32 / (BANDPASS_TAPS - 1) as f32).cos();
+
+            ideal * window
+        }).collect::<Vec<_>>();
+
+        let middle = PI * (low + high);
+        let gain = taps.iter().enumerate()
+            .map(|(index, tap)| tap * (middle * (index as f32 - center)).cos()).sum::<f32>();
+
+        Self {
+            taps: taps.iter().map(|tap| tap / gain).collect(),
+            buffer: VecDeque::from(vec![0.; BANDPASS_TAPS]),
+        }
+    }
+
+    pub fn push(&mut self, item: i16) -> i16 {
+        self.buffer.pop_front();
+        self.buffer.push_back(item as f32);
+
+        let value = self.buffer.iter().zip(self.taps.iter())
+            .map(|(sample, tap)| sample * tap).sum::<f32>();
+
+        value.max(-i16::MAX as f32).min(i16::MAX as f32) as i16
+    }
+}
+
+pub fn bandpass<I>(iter: I, low: f32, high: f32, sample_rate: u32) -> impl Iterator<Item=i16>
+    where I: Iterator<Item=i16>
+{
+    let mut filter = Bandpass::new(low, high, sample_rate);
+
+    iter.chain(vec![0; BANDPASS_TAPS - 1]).map(move |item| filter.push(item))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::athernet::{physical::PhyConfig, testing::{received, SEEDS}};
+
+    #[test]
+    fn round_trip() {
+        for (frequency, symbol_len) in [(12000., 5), (7000., 10), (15000., 16)].iter() {
+            let config = PhyConfig {
+                carrier_frequency: Some(*frequency), symbol_len: *symbol_len,
+                ..PhyConfig::default()
+            };
+            config.validate().unwrap();
+
+            let spec = "gain:0.0625,snr:25,delay:13";
+            assert_eq!(received(&config, spec, 100, 0.), SEEDS, "carrier {}", frequency);
+        }
+    }
+}
@@ -1,33 +1,195 @@
-use std::collections::VecDeque;
-use crate::athernet::mac::MacFrame;
+use std::{collections::VecDeque, sync::Arc};
+use crate::athernet::{
+    mac::MacFrame, fec::{Fec, FecDecoder}, scrambler::{Scrambler, Descrambler}, psk,
+    pulse::PulseShape, passband::{self, Bandpass, Downconverter}, backend::SAMPLE_RATE,
+    complex::Complex, ofdm::{self, OfdmConfig, OfdmModulate},
+};
 
 
 const SYMBOL_LEN: usize = 5;
 const BARKER: [bool; 7] = [true, true, true, false, false, true, false];
 
-pub const PHY_PAYLOAD_MAX: usize = 256;
+pub const PHY_PAYLOAD_MAX: usize = 4096;
 
-pub type PhyPayload = [u8; PHY_PAYLOAD_MAX];
+pub type PhyPayload = Vec<u8>;
 
-lazy_static!(
-    static ref CARRIER: [i16; SYMBOL_LEN] = {
-        let mut carrier = [0i16; SYMBOL_LEN];
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Modulation {
+    Bpsk,
+    Qpsk,
+    Dqpsk,
+    Ofdm(OfdmConfig),
+}
 
-        const ZERO: f32 = SYMBOL_LEN as f32 / 2. - 0.5;
+impl std::str::FromStr for Modulation {
+    type Err = String;
 
-        for i in 0..SYMBOL_LEN {
-            let t = (i as f32 - ZERO) * std::f32::consts::PI * 2. / SYMBOL_LEN as f32;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "bpsk" => Ok(Modulation::Bpsk),
+            "qpsk" => Ok(Modulation::Qpsk),
+            "dqpsk" => Ok(Modulation::Dqpsk),
+            "ofdm" => Ok(Modulation::Ofdm(OfdmConfig::default())),
+            _ => Err(format!("unknown modulation: {:?}", name)),
+        }
+    }
+}
 
-            let sinc = if t.abs() < 1e-6 { 1. } else { t.sin() / t };
+#[derive(Clone, PartialEq, Debug)]
+pub struct PhyConfig {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub symbol_len: usize,
+    pub pulse_shape: PulseShape,
+    pub roll_off: f32,
+    pub pulse_span: usize,
+    pub carrier_frequency: Option<f32>,
+    pub band: Option<usize>,
+    pub band_start: f32,
+    pub band_width: f32,
+    pub preamble: Vec<bool>,
+    pub payload_max: usize,
+    pub active_ratio: f32,
+    pub jamming_ratio: f32,
+    pub header_threshold_scale: i64,
+    pub timing_recovery: bool,
+    pub fec: Fec,
+    pub scrambler: Scrambler,
+    pub modulation: Modulation,
+}
 
-            carrier[i] = (sinc * std::i16::MAX as f32) as i16;
+impl Default for PhyConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+            symbol_len: SYMBOL_LEN,
+            pulse_shape: PulseShape::Sinc,
+            roll_off: 0.5,
+            pulse_span: 1,
+            carrier_frequency: None,
+            band: None,
+            band_start: 4000.,
+            band_width: 3000.,
+            preamble: BARKER.to_vec(),
+            payload_max: 256,
+            active_ratio: 4.,
+            jamming_ratio: 4.,
+            header_threshold_scale: 15000,
+            timing_recovery: true,
+            fec: Fec::None,
+            scrambler: Scrambler::None,
+            modulation: Modulation::Bpsk,
         }
+    }
+}
 
-        carrier
-    };
-);
+impl PhyConfig {
+    #[inline]
+    fn payload_symbol_len(&self) -> usize {
+        match self.modulation {
+            Modulation::Bpsk | Modulation::Qpsk | Modulation::Dqpsk => self.symbol_len,
+            Modulation::Ofdm(config) => config.symbol_len(),
+        }
+    }
+
+    #[inline]
+    fn pulse_len(&self) -> usize { self.pulse_shape.len(self.symbol_len, self.pulse_span) }
+
+    #[inline]
+    fn preamble_len(&self) -> usize {
+        self.symbol_len * self.preamble.len().saturating_sub(1) + self.pulse_len()
+    }
+
+    #[inline]
+    pub fn payload_max(&self) -> usize { std::cmp::min(self.payload_max, PHY_PAYLOAD_MAX) }
+
+    pub fn band_edges(&self) -> Option<(f32, f32)> {
+        self.band.map(|band| {
+            let low = self.band_start + band as f32 * self.band_width;
+            (low, low + self.band_width)
+        })
+    }
+
+    pub fn center_frequency(&self) -> Option<f32> {
+        match self.band_edges() {
+            Some((low, high)) => Some((low + high) / 2.),
+            None => self.carrier_frequency,
+        }
+    }
+
+    pub fn bandwidth(&self) -> f32 {
+        let symbol_rate = self.sample_rate as f32 / self.symbol_len as f32;
+        let pulse = symbol_rate * self.pulse_shape.bandwidth(self.roll_off);
+
+        let highest = match self.modulation {
+            Modulation::Bpsk => pulse,
+            Modulation::Qpsk | Modulation::Dqpsk => pulse.max(symbol_rate * 2.),
+            Modulation::Ofdm(config) => pulse.max(
+                (config.first_carrier + config.carrier_count) as f32 * self.sample_rate as f32
+                    / config.fft_len as f32
+            ),
+        };
+
+        if self.center_frequency().is_some() { highest * 2. } else { highest }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sample_rate == 0 { return Err("sample rate must be positive".into()); }
+
+        if self.symbol_len == 0 { return Err("symbol length must be positive".into()); }
+
+        if let Modulation::Ofdm(config) = self.modulation { config.validate()?; }
+
+        if let (None, Some(frequency)) = (self.band, self.carrier_frequency) {
+            let half = self.bandwidth() / 2.;
 
-fn carrier() -> impl Iterator<Item=i16> + 'static { CARRIER.iter().cloned() }
+            if frequency < half || frequency + half >= self.sample_rate as f32 / 2. {
+                return Err(format!(
+                    "a carrier at {} Hz needs {} Hz on either side within 0..{} Hz",
+                    frequency, half, self.sample_rate / 2,
+                ));
+            }
+        }
+
+        if let Some((_, high)) = self.band_edges() {
+            if high >= self.sample_rate as f32 / 2. {
+                return Err(format!(
+                    "band {} reaches {} Hz, past the {} Hz nyquist limit",
+                    self.band.unwrap(), high, self.sample_rate / 2,
+                ));
+            }
+        }
+
+        if self.band.is_some() && self.bandwidth() > self.band_width {
+            return Err(format!(
+                "signal needs {} Hz but a band is only {} Hz wide, use longer symbols",
+                self.bandwidth(), self.band_width,
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn carrier(&self) -> Arc<[i16]> {
+        self.pulse_shape.generate(self.symbol_len, self.pulse_span, self.roll_off)
+    }
+
+    pub fn turnaround(&self) -> usize {
+        let filter = if self.band.is_some() { 2 * passband::BANDPASS_TAPS } else { 0 };
+
+        2 * (self.preamble_len() + self.pulse_len()) + filter
+    }
+
+    pub fn signal_power(&self) -> f32 {
+        let carrier = self.carrier();
+
+        let power = carrier.iter().map(|item| *item as f32 * *item as f32).sum::<f32>()
+            / self.symbol_len as f32;
+
+        if self.center_frequency().is_some() { power / 2. } else { power }
+    }
+}
 
 
 pub struct ByteToBitIter<T> {
@@ -68,60 +230,129 @@ impl<T: Iterator<Item=u8>> Iterator for ByteToBitIter<T> {
 }
 
 
-fn pulse_shaping<I: Iterator<Item=bool>>(iter: I) -> impl Iterator<Item=i16> {
-    iter.map(move |bit| {
-        carrier().map(move |item| if bit { item } else { -item })
-    }).flatten()
+fn pulse_shaping<I: Iterator<Item=bool>>(
+    iter: I, carrier: Arc<[i16]>, symbol_len: usize,
+) -> impl Iterator<Item=i16> {
+    let mut buffer = VecDeque::with_capacity(carrier.len());
+
+    iter.map(Some).chain(std::iter::once(None)).flat_map(move |bit| {
+        let size = match bit {
+            Some(bit) => {
+                buffer.resize(std::cmp::max(buffer.len(), carrier.len()), 0);
+
+                for (sample, item) in buffer.iter_mut().zip(carrier.iter()) {
+                    *sample += if bit { *item as i32 } else { -(*item as i32) };
+                }
+
+                symbol_len
+            }
+            None => buffer.len(),
+        };
+
+        buffer.drain(..size).map(|sample| {
+            sample.max(-(i16::MAX as i32)).min(i16::MAX as i32) as i16
+        }).collect::<Vec<_>>()
+    })
 }
 
-pub fn modulate(buffer: MacFrame) -> impl Iterator<Item=i16> {
+pub fn modulate(buffer: MacFrame, config: &PhyConfig) -> Box<dyn Iterator<Item=i16> + Send> {
     let size = buffer.get_total_size();
     let raw = buffer.into_raw();
+    let carrier = config.carrier();
+
+    let preamble = pulse_shaping(
+        config.preamble.clone().into_iter(), carrier.clone(), config.symbol_len,
+    );
+    let bits = ByteToBitIter::from(raw.into_iter().take(size));
+    let bits = config.fec.encode(config.scrambler.scramble(bits));
+
+    let samples: Box<dyn Iterator<Item=i16> + Send> = match config.modulation {
+        Modulation::Bpsk => {
+            Box::new(preamble.chain(pulse_shaping(bits, carrier, config.symbol_len)))
+        }
+        Modulation::Qpsk => {
+            let carrier = psk::carrier(config.symbol_len);
+            Box::new(preamble.chain(psk::modulate(bits, false, carrier)))
+        }
+        Modulation::Dqpsk => {
+            let carrier = psk::carrier(config.symbol_len);
+            Box::new(preamble.chain(psk::modulate(bits, true, carrier)))
+        }
+        Modulation::Ofdm(config) => Box::new(preamble.chain(OfdmModulate::new(config, bits))),
+    };
 
-    pulse_shaping(BARKER.iter().cloned().chain(ByteToBitIter::from(
-        (0..size).map(move |index| raw[index])
-    )))
+    let samples: Box<dyn Iterator<Item=i16> + Send> = match config.center_frequency() {
+        Some(frequency) => Box::new(passband::upconvert(samples, frequency, config.sample_rate)),
+        None => samples,
+    };
+
+    match config.band_edges() {
+        Some((low, high)) => Box::new(passband::bandpass(samples, low, high, config.sample_rate)),
+        None => samples,
+    }
 }
 
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 struct BitReceive {
     inner: PhyPayload,
     count: usize,
     mac_addr: u8,
+    payload_max: usize,
+    decoder: FecDecoder,
+    descrambler: Descrambler,
 }
 
 impl BitReceive {
     #[inline]
-    pub fn new(mac_addr: u8) -> Self { Self { inner: [0; PHY_PAYLOAD_MAX], count: 0, mac_addr } }
+    pub fn new(mac_addr: u8, config: &PhyConfig) -> Self {
+        Self {
+            inner: Vec::new(),
+            count: 0,
+            mac_addr,
+            payload_max: config.payload_max(),
+            decoder: FecDecoder::new(config.fec),
+            descrambler: Descrambler::new(config.scrambler),
+        }
+    }
 
     #[inline]
-    pub fn push(&mut self, bit: bool) -> Option<Option<MacFrame>> {
+    pub fn push(&mut self, bit: bool) -> Option<Result<MacFrame, Abort>> {
+        let (bits, size) = self.decoder.push(bit)?;
+
+        for bit in bits[..size].iter() {
+            let bit = self.descrambler.push(*bit);
+            if let Some(result) = self.push_bit(bit) { return Some(result); }
+        }
+
+        None
+    }
+
+    #[inline]
+    fn push_bit(&mut self, bit: bool) -> Option<Result<MacFrame, Abort>> {
+        if self.count.is_multiple_of(8) { self.inner.push(0); }
         self.inner[self.count / 8] |= (bit as u8) << (self.count % 8);
         self.count += 1;
 
-        if self.count <= (MacFrame::MAC_DATA_SIZE + 1) * 8 {
-            None
-        } else {
-            let size = if (self.inner[MacFrame::OP_INDEX] & 0b1111) == MacFrame::OP_DATA {
-                self.inner[MacFrame::MAC_DATA_SIZE] as usize + 1 + 2
-            } else {
-                1
-            } + MacFrame::MAC_DATA_SIZE;
+        if !self.count.is_multiple_of(8) { return None; }
 
-            if size > PHY_PAYLOAD_MAX { return Some(None); }
+        let size = match MacFrame::frame_size(&self.inner)? {
+            Ok(size) if size > self.payload_max => return Some(Err(Abort::Oversize(size))),
+            Ok(size) => size,
+            Err(reason) => return Some(Err(reason)),
+        };
 
-            if self.count < size * 8 {
-                None
-            } else {
-                Some(Some(MacFrame::from_raw(self.inner)))
-            }
+        if self.count < size * 8 {
+            None
+        } else {
+            Some(Ok(MacFrame::from_raw(std::mem::take(&mut self.inner))))
         }
     }
 
     #[inline]
     pub fn is_self(&self) -> bool {
-        self.count < 4 || (self.inner[MacFrame::MAC_INDEX] & 0b1111) == self.mac_addr
+        self.count < (MacFrame::SRC_INDEX + 1) * 8 ||
+            self.inner[MacFrame::SRC_INDEX] == self.mac_addr
     }
 }
 
@@ -130,96 +361,496 @@ enum DemodulateState {
     RECEIVE(usize, BitReceive),
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Abort {
+    Jammed,
+    Version(u8),
+    HeaderCrc,
+    Oversize(usize),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct FrameInfo {
+    pub peak: i64,
+    pub snr: f32,
+    pub margin: f32,
+    pub timestamp: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Thresholds {
+    pub noise_floor: i64,
+    pub signal_level: Option<i64>,
+    pub active: i64,
+    pub jamming: i64,
+}
+
+impl std::fmt::Display for FrameInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "snr {:.1} dB, margin {:.2}, peak {}, sample {}",
+            self.snr, self.margin, self.peak, self.timestamp,
+        )
+    }
+}
+
+impl std::fmt::Display for Thresholds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "noise floor {}, ", self.noise_floor)?;
+        match self.signal_level {
+            Some(level) => write!(f, "signal level {}, ", level)?,
+            None => write!(f, "no signal yet, ")?,
+        }
+        write!(f, "active {}, jamming {}", self.active, self.jamming)
+    }
+}
+
 pub struct Demodulator {
     window: VecDeque<i16>,
+    baseband: VecDeque<Complex>,
+    bandpass: Option<Bandpass>,
+    downconverter: Option<Downconverter>,
+    carrier_phase: f32,
     state: DemodulateState,
     last_prod: i64,
+    prev_prod: i64,
     moving_average: i64,
+    noise_floor: f32,
+    signal_level: Option<f32>,
+    sample_count: u64,
+    info: FrameInfo,
+    aborted: Option<(u64, Abort)>,
+    margin_sum: f32,
+    margin_square_sum: f32,
+    margin_count: usize,
+    symbol_period: usize,
+    symbol_index: usize,
+    timing_error: f32,
+    timing_offset: f32,
+    phase: f32,
+    phase_origin: f32,
+    phase_reference: Complex,
     mac_addr: u8,
+    config: PhyConfig,
+    carrier: Arc<[i16]>,
+    carrier_energy: f32,
+    preamble: Box<[i16]>,
+    psk_carrier: Arc<[Complex]>,
+    referenced: bool,
 }
 
 impl Demodulator {
-    const PREAMBLE_LEN: usize = SYMBOL_LEN * BARKER.len();
-    const HEADER_THRESHOLD_SCALE: i64 = 1 << 19;
     const MOVING_AVERAGE: i64 = 16;
-    const ACTIVE_THRESHOLD: i64 = 512;
-    const JAMMING_THRESHOLD: i64 = 4096;
+    const NOISE_FLOOR_FALL: f32 = 1. / 2048.;
+    const NOISE_FLOOR_RISE: f32 = 1. / 4096.;
+    const NOISE_FLOOR_CLIP: f32 = 4.;
+    const TIMING_ERROR_LIMIT: f32 = 2.;
+    const TIMING_ERROR_LEAK: f32 = 1. / 16.;
+    const TIMING_TRAINING: usize = 8;
+    const PHASE_GAIN: f32 = 1. / 4.;
+    const DELAY_GAIN: f32 = 1. / 4.;
 
     fn dot_product<I: Iterator<Item=i16>, U: Iterator<Item=i16>>(iter_a: I, iter_b: U) -> i64 {
         iter_a.zip(iter_b).map(|(a, b)| a as i64 * b as i64).sum::<i64>()
     }
 
+    fn preamble_offset(&self) -> usize { self.window.len() - self.config.preamble_len() }
+
     fn preamble_product(&self) -> i64 {
-        Self::dot_product(self.window.iter().cloned(), pulse_shaping(BARKER.iter().cloned()))
+        if self.downconverter.is_some() {
+            return self.preamble_correlation().norm_sqr().sqrt() as i64;
+        }
+
+        Self::dot_product(
+            self.window.iter().skip(self.preamble_offset()).cloned(),
+            self.preamble.iter().cloned(),
+        )
+    }
+
+    fn preamble_correlation(&self) -> Complex {
+        let offset = self.baseband.len() - self.config.preamble_len();
+
+        self.baseband.iter().skip(offset).zip(self.preamble.iter())
+            .fold(Complex::default(), |sum, (sample, item)| sum + sample.scale(*item as f32))
+    }
+
+    fn project(value: Complex, phase: f32) -> i16 {
+        let value = (value * Complex::from_polar(1., -phase)).re;
+        value.max(-i16::MAX as f32).min(i16::MAX as f32) as i16
+    }
+
+    fn align_carrier(&mut self) {
+        if self.downconverter.is_none() { return; }
+
+        self.carrier_phase = self.preamble_correlation().arg();
+
+        let skip = self.baseband.len() - self.window.len();
+        let phase = self.carrier_phase;
+
+        self.window = self.baseband.iter().skip(skip)
+            .map(|value| Self::project(*value, phase)).collect();
+    }
+
+    fn track_carrier(&mut self) {
+        if self.downconverter.is_none() { return; }
+
+        let square = self.baseband.iter().rev().take(self.config.payload_symbol_len())
+            .fold(Complex::default(), |sum, value| sum + *value * *value);
+
+        let half = std::f32::consts::FRAC_PI_2;
+        let error = square.arg() / 2. - self.carrier_phase;
+        let error = (error + half).rem_euclid(std::f32::consts::PI) - half;
+
+        self.carrier_phase += error * Self::PHASE_GAIN;
+    }
+
+    fn preamble_matches(&mut self) -> bool {
+        self.align_carrier();
+
+        self.config.preamble.iter().enumerate().all(|(index, bit)| {
+            let offset = self.preamble_offset() + index * self.config.symbol_len;
+            *bit == (self.section_product(offset) > 0)
+        })
     }
 
     fn section_product(&self, offset: usize) -> i64 {
-        Self::dot_product(self.window.iter().skip(offset).cloned(), carrier())
+        Self::dot_product(self.window.iter().skip(offset).cloned(), self.carrier.iter().cloned())
+    }
+
+    fn demap(&mut self) -> Vec<f32> {
+        self.track_carrier();
+
+        let offset = self.window.len() - self.config.payload_symbol_len() - 1;
+        let symbol = self.window.iter().skip(offset).cloned();
+
+        self.symbol_period = self.config.payload_symbol_len();
+
+        let result = match self.config.modulation {
+            Modulation::Bpsk => {
+                let offset = self.window.len() - self.carrier.len() - Self::gate(&self.config);
+                self.track_early_late(offset);
+                vec![self.section_product(offset) as f32 / self.carrier_energy]
+            }
+            Modulation::Qpsk => {
+                let value = psk::correlate(symbol, &self.psk_carrier);
+                let corrected = value * Complex::from_polar(1., -self.phase);
+
+                self.track_phase(value, psk::map(corrected.re > 0., corrected.im > 0.));
+
+                vec![corrected.re, corrected.im]
+            }
+            Modulation::Dqpsk => {
+                let value = psk::correlate(symbol, &self.psk_carrier);
+
+                if std::mem::replace(&mut self.referenced, true) {
+                    let diff = value * Complex::from_polar(1., -self.phase)
+                        * self.phase_reference.conj();
+
+                    self.phase_reference = self.phase_reference
+                        * psk::map(diff.re > 0., diff.im > 0.);
+                    self.track_phase(value, self.phase_reference);
+
+                    vec![diff.re, diff.im]
+                } else {
+                    self.phase_reference = value.scale(1. / value.norm_sqr().sqrt());
+                    Vec::new()
+                }
+            }
+            Modulation::Ofdm(config) => {
+                let (result, delay) = ofdm::demodulate(&config, symbol);
+                self.track_delay(delay);
+                result
+            }
+        };
+
+        for value in result.iter() {
+            self.margin_sum += value.abs();
+            self.margin_square_sum += value * value;
+            self.margin_count += 1;
+        }
+
+        result
+    }
+
+    fn peak_offset(&self, next: i64) -> f32 {
+        let (before, peak, after) = (self.prev_prod as f32, self.last_prod as f32, next as f32);
+        let curvature = before - 2. * peak + after;
+
+        if curvature < 0. { (after - before) / (2. * curvature) } else { 0. }
+    }
+
+    fn track_early_late(&mut self, offset: usize) {
+        if !self.config.timing_recovery { return; }
+
+        let gate = Self::gate(&self.config);
+        let early = (self.section_product(offset - gate) as f32).abs();
+        let late = (self.section_product(offset + gate) as f32).abs();
+
+        self.timing_error *= 1. - Self::TIMING_ERROR_LEAK;
+
+        if early + late > 0. { self.timing_error += (late - early) / (early + late); }
+
+        if self.timing_error > Self::TIMING_ERROR_LIMIT {
+            self.timing_error = 0.;
+            self.symbol_period += 1;
+        } else if self.timing_error < -Self::TIMING_ERROR_LIMIT {
+            self.timing_error = 0.;
+            self.symbol_period -= 1;
+        }
+    }
+
+    fn track_delay(&mut self, delay: f32) {
+        if !self.config.timing_recovery { return; }
+
+        self.timing_error += (delay - self.timing_error) * Self::DELAY_GAIN;
+
+        if self.timing_error > 0.5 {
+            self.timing_error -= 1.;
+            self.symbol_period -= 1;
+        } else if self.timing_error < -0.5 {
+            self.timing_error += 1.;
+            self.symbol_period += 1;
+        }
+    }
+
+    fn track_phase(&mut self, value: Complex, expected: Complex) {
+        if !self.config.timing_recovery { return; }
+
+        let quadrant = std::f32::consts::FRAC_PI_2;
+        let error = (value * expected.conj()).arg() - self.phase;
+        let error = (error + quadrant / 2.).rem_euclid(quadrant) - quadrant / 2.;
+
+        self.phase += error * Self::PHASE_GAIN;
+
+        let step = 2. * std::f32::consts::PI / self.config.symbol_len as f32;
+
+        if self.symbol_index < Self::TIMING_TRAINING {
+            self.symbol_index += 1;
+            self.phase_origin = self.phase - self.timing_offset * step;
+            return;
+        }
+
+        let drift = self.phase - self.phase_origin;
+
+        if drift > step / 2. {
+            self.symbol_period -= 1;
+            self.phase -= step;
+        } else if drift < -step / 2. {
+            self.symbol_period += 1;
+            self.phase += step;
+        }
+    }
+
+    fn frame_info(&self) -> FrameInfo {
+        let count = std::cmp::max(self.margin_count, 1) as f32;
+        let margin = self.margin_sum / count;
+        let variance = self.margin_square_sum / count - margin * margin;
+
+        let snr = if variance > 0. {
+            10. * (margin * margin / variance).log10()
+        } else {
+            f32::INFINITY
+        };
+
+        FrameInfo { snr, margin, ..self.info }
     }
 
     fn moving_average(last: i64, new: i64) -> i64 {
-        (last * (Self::MOVING_AVERAGE - 1) + new) / Self::MOVING_AVERAGE
+        (last * (Self::MOVING_AVERAGE - 1) + new + Self::MOVING_AVERAGE / 2) / Self::MOVING_AVERAGE
     }
 
-    pub fn new(mac_addr: u8) -> Self {
+    fn update_noise_floor(&mut self, item: i16) {
+        let level = (item as f32).abs().min(self.noise_floor.max(1.) * Self::NOISE_FLOOR_CLIP);
+        let rate = if level < self.noise_floor {
+            Self::NOISE_FLOOR_FALL
+        } else {
+            Self::NOISE_FLOOR_RISE
+        };
+
+        self.noise_floor += (level - self.noise_floor) * rate;
+    }
+
+    fn update_signal_level(&mut self) {
+        let level = self.window.iter().skip(self.preamble_offset())
+            .map(|item| (*item as f32).abs()).sum::<f32>() / self.config.preamble_len() as f32;
+
+        self.signal_level = Some(level);
+    }
+
+    fn active_threshold(&self) -> i64 { (self.noise_floor * self.config.active_ratio) as i64 }
+
+    fn jamming_threshold(&self) -> i64 {
+        match self.signal_level {
+            Some(level) => std::cmp::max(
+                self.active_threshold(), (level * self.config.jamming_ratio) as i64,
+            ),
+            None => i64::MAX,
+        }
+    }
+
+    pub fn take_aborted(&mut self) -> Option<(u64, Abort)> { self.aborted.take() }
+
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            noise_floor: self.noise_floor as i64,
+            signal_level: self.signal_level.map(|level| level as i64),
+            active: self.active_threshold(),
+            jamming: self.jamming_threshold(),
+        }
+    }
+
+    fn gate(config: &PhyConfig) -> usize { std::cmp::max(config.symbol_len / 4, 1) }
+
+    fn window_len(config: &PhyConfig) -> usize {
+        std::cmp::max(
+            config.preamble_len(),
+            std::cmp::max(
+                config.payload_symbol_len() + 2, config.pulse_len() + 2 * Self::gate(config),
+            ),
+        )
+    }
+
+    pub fn new(mac_addr: u8, config: PhyConfig) -> Self {
+        let carrier = config.carrier();
+
         Self {
-            window: VecDeque::with_capacity(Self::PREAMBLE_LEN),
+            window: VecDeque::with_capacity(Self::window_len(&config)),
+            baseband: VecDeque::with_capacity(Self::window_len(&config)),
+            bandpass: config.band_edges()
+                .map(|(low, high)| Bandpass::new(low, high, config.sample_rate)),
+            downconverter: config.center_frequency()
+                .map(|frequency| Downconverter::new(frequency, config.sample_rate)),
+            carrier_phase: 0.,
             state: DemodulateState::WAITE,
             last_prod: 0,
+            prev_prod: 0,
             moving_average: 0,
+            noise_floor: 0.,
+            signal_level: None,
+            sample_count: 0,
+            info: FrameInfo { peak: 0, snr: 0., margin: 0., timestamp: 0 },
+            aborted: None,
+            margin_sum: 0.,
+            margin_square_sum: 0.,
+            margin_count: 0,
+            symbol_period: config.payload_symbol_len(),
+            symbol_index: 0,
+            timing_error: 0.,
+            timing_offset: 0.,
+            phase: 0.,
+            phase_origin: 0.,
+            phase_reference: Complex::default(),
             mac_addr,
+            preamble: pulse_shaping(
+                config.preamble.clone().into_iter(), carrier.clone(), config.symbol_len,
+            ).collect(),
+            psk_carrier: psk::carrier(config.symbol_len),
+            carrier_energy: carrier.iter().map(|item| *item as f32 * *item as f32).sum::<f32>()
+                / i16::MAX as f32,
+            carrier,
+            config,
+            referenced: false,
         }
     }
 
     pub fn is_active(&self) -> bool {
-        if self.moving_average > Self::JAMMING_THRESHOLD { return true; }
+        if self.moving_average > self.jamming_threshold() { return true; }
 
-        if let DemodulateState::RECEIVE(_, receiver) = self.state {
+        if let DemodulateState::RECEIVE(_, ref receiver) = self.state {
             !receiver.is_self()
         } else {
             false
         }
     }
 
-    pub fn push_back(&mut self, item: i16) -> Option<MacFrame> {
-        if self.window.len() == Self::PREAMBLE_LEN { self.window.pop_front(); }
-        self.window.push_back(item);
+    pub fn push_back(&mut self, item: i16) -> Option<(MacFrame, FrameInfo)> {
+        self.sample_count += 1;
+
+        let item = match self.bandpass {
+            Some(ref mut bandpass) => bandpass.push(item),
+            None => item,
+        };
+
+        let sample = match self.downconverter {
+            Some(ref mut downconverter) => {
+                let value = downconverter.push(item);
+
+                if self.baseband.len() == Self::window_len(&self.config) {
+                    self.baseband.pop_front();
+                }
+                self.baseband.push_back(value);
+
+                Self::project(value, self.carrier_phase)
+            }
+            None => item,
+        };
+
+        if self.window.len() == Self::window_len(&self.config) { self.window.pop_front(); }
+        self.window.push_back(sample);
 
         self.moving_average = Self::moving_average(self.moving_average, (item as i64).abs());
-        let threshold = self.moving_average * Self::HEADER_THRESHOLD_SCALE;
+        let threshold = self.moving_average * self.config.header_threshold_scale
+            * self.config.preamble_len() as i64;
         let mut prod = 0;
 
-        match self.state {
+        match std::mem::replace(&mut self.state, DemodulateState::WAITE) {
             DemodulateState::WAITE => {
-                if self.window.len() == Self::PREAMBLE_LEN &&
-                    self.moving_average > Self::ACTIVE_THRESHOLD {
+                self.update_noise_floor(item);
+
+                if self.window.len() >= self.config.preamble_len() &&
+                    self.moving_average > self.active_threshold() {
                     prod = self.preamble_product();
 
-                    if prod > threshold && self.last_prod > prod && BARKER.iter()
-                        .enumerate().all(|(index, bit)| {
-                        *bit == (self.section_product(index * SYMBOL_LEN) > 0)
-                    }) {
-                        self.state = DemodulateState::RECEIVE(0, BitReceive::new(self.mac_addr));
+                    if prod > threshold && self.last_prod > prod && self.preamble_matches() {
+                        self.state = DemodulateState::RECEIVE(
+                            0, BitReceive::new(self.mac_addr, &self.config),
+                        );
+                        self.referenced = false;
+                        self.update_signal_level();
+                        self.info.peak = self.last_prod;
+                        self.info.timestamp = self.sample_count;
+                        self.margin_sum = 0.;
+                        self.margin_square_sum = 0.;
+                        self.margin_count = 0;
+                        self.symbol_period = match self.config.modulation {
+                            Modulation::Bpsk => {
+                                self.carrier.len() + Self::gate(&self.config) - 1
+                            }
+                            _ => self.config.payload_symbol_len(),
+                        };
+                        self.symbol_index = 0;
+                        self.timing_error = 0.;
+                        self.timing_offset = self.peak_offset(prod);
+                        self.phase = 0.;
+                        self.phase_origin = 0.;
                         prod = 0;
                     }
                 }
             }
             DemodulateState::RECEIVE(mut count, mut buffer) => {
-                if self.moving_average > Self::JAMMING_THRESHOLD {
+                if self.moving_average > self.jamming_threshold() {
                     self.state = DemodulateState::WAITE;
                     self.window.clear();
+                    self.aborted = Some((self.info.timestamp, Abort::Jammed));
                     return None;
                 }
 
                 count += 1;
 
-                self.state = if count == SYMBOL_LEN {
-                    let prod = self.section_product(Self::PREAMBLE_LEN - SYMBOL_LEN);
+                self.state = if count == self.symbol_period {
+                    for value in self.demap() {
+                        if let Some(result) = buffer.push(value > 0.) {
+                            self.state = DemodulateState::WAITE;
+                            self.window.clear();
 
-                    if let Some(result) = buffer.push(prod > 0) {
-                        self.state = DemodulateState::WAITE;
-                        self.window.clear();
-                        return result;
+                            return match result {
+                                Ok(frame) => Some((frame, self.frame_info())),
+                                Err(reason) => {
+                                    self.aborted = Some((self.info.timestamp, reason));
+                                    None
+                                }
+                            };
+                        }
                     }
 
                     DemodulateState::RECEIVE(0, buffer)
@@ -229,8 +860,194 @@ impl Demodulator {
             }
         }
 
+        self.prev_prod = self.last_prod;
         self.last_prod = prod;
 
         None
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::athernet::{channel::Channel, testing::{payload, received, trace, SEEDS}};
+
+    const SPEC: &str = "gain:0.0625,snr:30";
+
+    fn receive_raw(config: &PhyConfig, raw: PhyPayload) -> (Option<MacFrame>, Option<Abort>) {
+        let mut demodulator = Demodulator::new(2, config.clone());
+        let mut frame = None;
+        let mut aborted = None;
+
+        let mut samples = vec![0; 100];
+        samples.extend(modulate(MacFrame::from_raw(raw), config).map(|item| item / 16));
+        samples.resize(samples.len() + 100, 0);
+
+        for sample in samples {
+            frame = demodulator.push_back(sample).map(|(frame, _)| frame).or(frame);
+            aborted = demodulator.take_aborted().map(|(_, reason)| reason).or(aborted);
+        }
+
+        (frame, aborted)
+    }
+
+    #[test]
+    fn aborted_reception() {
+        let config = PhyConfig::default();
+        let raw = MacFrame::new_data(1, 2, 0, &[0x5a; 100]).into_raw();
+
+        let (frame, aborted) = receive_raw(&config, raw.clone());
+        assert!(frame.unwrap().check(2));
+        assert_eq!(aborted, None);
+
+        let mut corrupted = raw.clone();
+        corrupted[MacFrame::HEADER_CRC_INDEX] ^= 1;
+        assert!(matches!(receive_raw(&config, corrupted), (None, Some(Abort::HeaderCrc))));
+
+        let mut corrupted = raw.clone();
+        corrupted[MacFrame::VERSION_INDEX] = MacFrame::VERSION + 1;
+        assert!(matches!(
+            receive_raw(&config, corrupted), (None, Some(Abort::Version(version)))
+                if version == MacFrame::VERSION + 1
+        ));
+
+        let small = PhyConfig { payload_max: 50, ..PhyConfig::default() };
+        let size = raw.len();
+        assert!(matches!(
+            receive_raw(&small, raw), (None, Some(Abort::Oversize(oversize))) if oversize == size
+        ));
+    }
+
+    #[test]
+    fn zero_rates_rejected() {
+        assert!(PhyConfig { sample_rate: 0, ..PhyConfig::default() }.validate().is_err());
+        assert!(PhyConfig { symbol_len: 0, ..PhyConfig::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn carrier_out_of_range() {
+        let config = |frequency| PhyConfig {
+            carrier_frequency: Some(frequency), ..PhyConfig::default()
+        };
+
+        assert!(config(12000.).validate().is_ok());
+        assert!(config(30000.).validate().is_err());
+        assert!(config(16000.).validate().is_err());
+        assert!(config(4000.).validate().is_err());
+    }
+
+    #[test]
+    fn band_too_narrow() {
+        let config = PhyConfig { band: Some(0), ..PhyConfig::default() };
+        assert!(config.validate().is_err());
+
+        let config = PhyConfig {
+            symbol_len: 32, pulse_shape: PulseShape::RootRaisedCosine, roll_off: 0.35, ..config
+        };
+        assert!(config.validate().is_ok());
+
+        assert!(PhyConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn band_past_nyquist() {
+        let config = |band| PhyConfig {
+            band: Some(band), symbol_len: 32, pulse_shape: PulseShape::RootRaisedCosine,
+            roll_off: 0.35, ..PhyConfig::default()
+        };
+
+        assert!(config(5).validate().is_ok());
+        assert!(config(6).validate().is_err());
+        assert!(config(7).validate().is_err());
+        assert!(PhyConfig { sample_rate: 16000, ..config(1) }.validate().is_err());
+    }
+
+    #[test]
+    fn ofdm_config_checked() {
+        let config = |ofdm| PhyConfig {
+            modulation: Modulation::Ofdm(ofdm), ..PhyConfig::default()
+        };
+        let ofdm = OfdmConfig::default();
+
+        assert!(config(ofdm).validate().is_ok());
+        assert!(config(OfdmConfig { fft_len: 96, ..ofdm }).validate().is_err());
+        assert!(config(OfdmConfig { cyclic_prefix: 65, ..ofdm }).validate().is_err());
+        assert!(config(OfdmConfig { first_carrier: 0, ..ofdm }).validate().is_err());
+        assert!(config(OfdmConfig { pilot_interval: 0, ..ofdm }).validate().is_err());
+        assert!(config(OfdmConfig { carrier_count: 30, ..ofdm }).validate().is_err());
+        assert!(config(OfdmConfig { carrier_count: 29, ..ofdm }).validate().is_ok());
+        assert!(config(OfdmConfig { carrier_count: 1, ..ofdm }).validate().is_err());
+    }
+
+    #[test]
+    fn ofdm_clock_drift() {
+        let config = PhyConfig { modulation: "ofdm".parse().unwrap(), ..PhyConfig::default() };
+
+        for ppm in [0., 500., 1000., 2000., -500., -1000., -2000.].iter() {
+            assert_eq!(received(&config, SPEC, 200, *ppm), SEEDS, "ppm {}", ppm);
+        }
+    }
+
+    #[test]
+    fn bpsk_clock_drift() {
+        let config = PhyConfig { payload_max: PHY_PAYLOAD_MAX, ..PhyConfig::default() };
+
+        for ppm in [1000., -1000.].iter() {
+            assert_eq!(received(&config, SPEC, 4000, *ppm), SEEDS, "ppm {}", ppm);
+        }
+    }
+
+    #[test]
+    fn band_clock_drift() {
+        let config = PhyConfig {
+            band: Some(0), symbol_len: 32, pulse_shape: PulseShape::RootRaisedCosine,
+            roll_off: 0.35, pulse_span: 4, ..PhyConfig::default()
+        };
+
+        for ppm in [0., 200., 1000., -200., -1000.].iter() {
+            assert_eq!(received(&config, SPEC, 200, *ppm), SEEDS, "ppm {}", ppm);
+        }
+    }
+
+    #[test]
+    fn adaptive_thresholds() {
+        let config = PhyConfig::default();
+        let payload = payload(100);
+        let samples = trace(&config, &payload, 1);
+        let mut active = -1;
+
+        for (seed, deviation) in [0., 2., 16., 128., 1024.].iter().enumerate() {
+            let mut demodulator = Demodulator::new(2, config.clone());
+            let mut noise = Channel::new(&config);
+            noise.set_seed(seed as u64).set_snr(0.).set_signal_power(deviation * deviation);
+
+            let listen = |demodulator: &mut Demodulator, noise: &mut Channel| {
+                for _ in 0..20000 {
+                    demodulator.push_back(noise.push_back(0.));
+                    assert!(!demodulator.is_active(), "noise {}", deviation);
+                }
+            };
+
+            listen(&mut demodulator, &mut noise);
+
+            let thresholds = demodulator.thresholds();
+            let floor = thresholds.noise_floor as f32;
+            assert!(floor >= deviation / 2. - 1. && floor <= deviation * 0.8 + 1., "{}", floor);
+            assert!(thresholds.active > active, "noise {}: {}", deviation, thresholds);
+            active = thresholds.active;
+
+            for gain in [1. / 256., 1. / 32., 1. / 4.].iter() {
+                if i16::MAX as f32 * gain < 4. * deviation { continue; }
+
+                let decoded = samples.iter()
+                    .map(|sample| noise.push_back(*sample as f32 * gain))
+                    .filter_map(|sample| demodulator.push_back(sample))
+                    .any(|(frame, _)| frame.check(2) && *frame.unwrap() == payload[..]);
+                assert!(decoded, "noise {}, gain {}", deviation, gain);
+            }
+
+            listen(&mut demodulator, &mut noise);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use crate::athernet::complex::Complex;
+
+
+pub fn carrier(symbol_len: usize) -> Arc<[Complex]> {
+    (0..symbol_len).map(|index| {
+        Complex::from_polar(1., index as f32 * std::f32::consts::PI * 2. / symbol_len as f32)
+    }).collect()
+}
+
+fn symbol(phase: Complex, carrier: Arc<[Complex]>) -> impl Iterator<Item=i16> {
+    (0..carrier.len()).map(move |index| {
+        ((carrier[index] * phase).re * i16::MAX as f32) as i16
+    })
+}
+
+pub fn map(first: bool, second: bool) -> Complex {
+    let amplitude = std::f32::consts::FRAC_1_SQRT_2;
+
+    Complex::new(
+        if first { amplitude } else { -amplitude },
+        if second { amplitude } else { -amplitude },
+    )
+}
+
+pub fn modulate<I>(
+    mut iter: I, differential: bool, carrier: Arc<[Complex]>,
+) -> impl Iterator<Item=i16> where I: Iterator<Item=bool> {
+    let mut phase = Complex::new(1., 0.);
+    let reference = if differential { Some(phase) } else { None };
+
+    reference.into_iter().chain(std::iter::from_fn(move || {
+        let first = iter.next()?;
+        let value = map(first, iter.next().unwrap_or(false));
+
+        phase = if differential { phase * value } else { value };
+
+        Some(phase)
+    })).flat_map(move |phase| symbol(phase, carrier.clone()))
+}
+
+pub fn correlate<I: Iterator<Item=i16>>(symbol: I, carrier: &[Complex]) -> Complex {
+    symbol.zip(carrier.iter()).fold(Complex::default(), |sum, (sample, item)| {
+        sum + item.conj().scale(sample as f32)
+    }).scale(2. / carrier.len() as f32)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::athernet::{
+        physical::{Modulation, PhyConfig, PHY_PAYLOAD_MAX}, testing::{received, SEEDS},
+    };
+
+    const SYMBOL_LEN: usize = 8;
+
+    fn bits() -> Vec<bool> { (0..64).map(|index| (index * 5) % 7 < 3).collect() }
+
+    fn symbols(differential: bool, rotation: f32) -> Vec<Complex> {
+        let rotation = Complex::from_polar(1., rotation);
+        let rotated = carrier(SYMBOL_LEN).iter().map(|item| *item * rotation).collect();
+        let samples = modulate(bits().into_iter(), differential, rotated).collect::<Vec<_>>();
+
+        samples.chunks(SYMBOL_LEN)
+            .map(|symbol| correlate(symbol.iter().cloned(), &carrier(SYMBOL_LEN)))
+            .collect()
+    }
+
+    fn demap(values: impl Iterator<Item=Complex>) -> Vec<bool> {
+        values.flat_map(|value| vec![value.re > 0., value.im > 0.]).collect()
+    }
+
+    fn qpsk(rotation: f32) -> Vec<bool> { demap(symbols(false, rotation).into_iter()) }
+
+    fn dqpsk(rotation: f32) -> Vec<bool> {
+        let symbols = symbols(true, rotation);
+        demap(symbols.windows(2).map(|pair| pair[1] * pair[0].conj()))
+    }
+
+    #[test]
+    fn map_quadrants() {
+        for (first, second) in [(false, false), (false, true), (true, false), (true, true)].iter() {
+            let value = map(*first, *second);
+
+            assert!((value.norm_sqr() - 1.).abs() < 1e-6);
+            assert_eq!((value.re > 0., value.im > 0.), (*first, *second));
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(symbols(false, 0.).len(), bits().len() / 2);
+        assert_eq!(symbols(true, 0.).len(), bits().len() / 2 + 1);
+
+        assert_eq!(qpsk(0.), bits());
+        assert_eq!(dqpsk(0.), bits());
+    }
+
+    #[test]
+    fn dqpsk_ignores_phase_rotation() {
+        for rotation in [1., 2., -2.5, 3.].iter() {
+            assert_ne!(qpsk(*rotation), bits(), "rotation {}", rotation);
+            assert_eq!(dqpsk(*rotation), bits(), "rotation {}", rotation);
+        }
+    }
+
+    fn clock_drift(modulation: Modulation) {
+        let config = PhyConfig { modulation, payload_max: PHY_PAYLOAD_MAX, ..PhyConfig::default() };
+
+        for ppm in [0., 500., 1000., -500., -1000.].iter() {
+            let count = received(&config, "gain:0.0625,snr:30", 4000, *ppm);
+            assert_eq!(count, SEEDS, "{:?} ppm {}", modulation, ppm);
+        }
+    }
+
+    #[test]
+    fn qpsk_clock_drift() { clock_drift(Modulation::Qpsk); }
+
+    #[test]
+    fn dqpsk_clock_drift() { clock_drift(Modulation::Dqpsk); }
+}
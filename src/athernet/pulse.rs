@@ -0,0 +1,132 @@
+use std::{f32::consts::PI, sync::Arc};
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PulseShape {
+    Rectangular,
+    Sinc,
+    RaisedCosine,
+    RootRaisedCosine,
+}
+
+impl std::str::FromStr for PulseShape {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "rect" => Ok(PulseShape::Rectangular),
+            "sinc" => Ok(PulseShape::Sinc),
+            "rc" => Ok(PulseShape::RaisedCosine),
+            "rrc" => Ok(PulseShape::RootRaisedCosine),
+            _ => Err(format!("unknown pulse shape: {:?}", name)),
+        }
+    }
+}
+
+impl PulseShape {
+    pub fn len(self, symbol_len: usize, span: usize) -> usize {
+        match self {
+            PulseShape::Rectangular => symbol_len,
+            _ => symbol_len * std::cmp::max(span, 1),
+        }
+    }
+
+    pub fn bandwidth(self, roll_off: f32) -> f32 {
+        match self {
+            PulseShape::Rectangular | PulseShape::Sinc => 1.,
+            PulseShape::RaisedCosine | PulseShape::RootRaisedCosine => (1. + roll_off) / 2.,
+        }
+    }
+
+    pub fn generate(self, symbol_len: usize, span: usize, roll_off: f32) -> Arc<[i16]> {
+        let len = self.len(symbol_len, span);
+        let zero = len as f32 / 2. - 0.5;
+        let peak = self.value(0., roll_off);
+
+        (0..len).map(|index| {
+            let time = (index as f32 - zero) / symbol_len as f32;
+
+            (self.value(time, roll_off) / peak * i16::MAX as f32) as i16
+        }).collect()
+    }
+
+    fn value(self, time: f32, roll_off: f32) -> f32 {
+        match self {
+            PulseShape::Rectangular => 1.,
+            PulseShape::Sinc => sinc(time * 2.),
+            PulseShape::RaisedCosine => raised_cosine(time, roll_off),
+            PulseShape::RootRaisedCosine => root_raised_cosine(time, roll_off),
+        }
+    }
+}
+
+
+fn sinc(time: f32) -> f32 {
+    let t = time * PI;
+
+    if t.abs() < 1e-6 { 1. } else { t.sin() / t }
+}
+
+fn raised_cosine(time: f32, roll_off: f32) -> f32 {
+    let denominator = 1. - (2. * roll_off * time).powi(2);
+
+    if denominator.abs() < 1e-6 {
+        PI / 4. * sinc(1. / (2. * roll_off))
+    } else {
+        sinc(time) * (PI * roll_off * time).cos() / denominator
+    }
+}
+
+fn root_raised_cosine(time: f32, roll_off: f32) -> f32 {
+    if time.abs() < 1e-6 { return 1. - roll_off + 4. * roll_off / PI; }
+
+    let denominator = PI * time * (1. - (4. * roll_off * time).powi(2));
+
+    if denominator.abs() < 1e-6 {
+        let angle = PI / (4. * roll_off);
+
+        roll_off / 2f32.sqrt()
+            * ((1. + 2. / PI) * angle.sin() + (1. - 2. / PI) * angle.cos())
+    } else {
+        ((PI * time * (1. - roll_off)).sin()
+            + 4. * roll_off * time * (PI * time * (1. + roll_off)).cos()) / denominator
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHAPES: [PulseShape; 4] = [
+        PulseShape::Rectangular, PulseShape::Sinc, PulseShape::RaisedCosine,
+        PulseShape::RootRaisedCosine,
+    ];
+
+    #[test]
+    fn symmetric_taps() {
+        for shape in SHAPES.iter() {
+            for (symbol_len, span) in [(5, 1), (8, 4), (7, 6)].iter() {
+                let taps = shape.generate(*symbol_len, *span, 0.35);
+
+                assert_eq!(taps.len(), shape.len(*symbol_len, *span));
+
+                for (left, right) in taps.iter().zip(taps.iter().rev()) {
+                    assert!((*left as i32 - *right as i32).abs() <= 1, "{:?}", shape);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn normalised_taps() {
+        for shape in SHAPES.iter() {
+            for roll_off in [0.1, 0.35, 0.5, 1.].iter() {
+                let taps = shape.generate(9, 3, *roll_off);
+
+                assert_eq!(taps.iter().map(|tap| tap.abs()).max(), Some(i16::MAX), "{:?}", shape);
+                assert_eq!(taps[taps.len() / 2], i16::MAX, "{:?}", shape);
+            }
+        }
+    }
+}
@@ -1,14 +1,28 @@
-use std::{ffi::c_void, ops::Deref};
+use std::{ffi::{c_void, CStr}, ops::Deref, os::raw::c_char};
+use crate::athernet::backend::SampleBackend;
+
+
+const DEVICE_SAMPLE_RATES: usize = 16;
+const DEVICE_NAME_LENGTH: usize = 512;
+const DEFAULT_DEVICE: i32 = -1;
+
+const NATIVE_FORMATS: [(u32, &str); 6] = [
+    (0x01, "i8"), (0x02, "i16"), (0x04, "i24"), (0x08, "i32"), (0x10, "f32"), (0x20, "f64"),
+];
 
 
 #[derive(std::fmt::Debug)]
 pub enum StreamError {
     UnknownError,
+    DeviceNotFound(String),
 }
 
 impl std::fmt::Display for StreamError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("RTAudio stream error!")
+        match self {
+            StreamError::UnknownError => f.write_str("RTAudio stream error!"),
+            StreamError::DeviceNotFound(name) => write!(f, "audio device {:?} not found!", name),
+        }
     }
 }
 
@@ -29,18 +43,139 @@ impl Drop for Stream {
     fn drop(&mut self) { unsafe { rtaudio_destroy_stream(self.inner); } }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawDeviceInfo {
+    probed: u32,
+    output_channels: u32,
+    input_channels: u32,
+    duplex_channels: u32,
+    is_default_output: u32,
+    is_default_input: u32,
+    native_formats: u32,
+    preferred_sample_rate: u32,
+    sample_rates: [u32; DEVICE_SAMPLE_RATES],
+    name: [c_char; DEVICE_NAME_LENGTH],
+}
+
 extern "C" {
-    fn rtaudio_print_hosts();
+    fn rtaudio_get_devices(devices: *mut RawDeviceInfo, capacity: i32) -> i32;
 
-    fn rtaudio_create_output_stream(callback: *const c_void, data: *mut c_void) -> *mut c_void;
+    fn rtaudio_create_output_stream(
+        callback: *const c_void, data: *mut c_void, sample_rate: u32, channels: u32, device: i32,
+    ) -> *mut c_void;
 
-    fn rtaudio_create_input_stream(callback: *const c_void, data: *mut c_void) -> *mut c_void;
+    fn rtaudio_create_input_stream(
+        callback: *const c_void, data: *mut c_void, sample_rate: u32, channels: u32, device: i32,
+    ) -> *mut c_void;
 
     fn rtaudio_destroy_stream(stream: *mut c_void);
 }
 
-#[allow(dead_code)]
-pub fn print_hosts() { unsafe { rtaudio_print_hosts() } }
+
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub index: u32,
+    pub name: String,
+    pub output_channels: u32,
+    pub input_channels: u32,
+    pub duplex_channels: u32,
+    pub is_default_output: bool,
+    pub is_default_input: bool,
+    pub native_formats: Vec<&'static str>,
+    pub preferred_sample_rate: u32,
+    pub sample_rates: Vec<u32>,
+}
+
+impl DeviceInfo {
+    fn from_raw(index: u32, raw: &RawDeviceInfo) -> Self {
+        Self {
+            index,
+            name: unsafe { CStr::from_ptr(raw.name.as_ptr()) }.to_string_lossy().into_owned(),
+            output_channels: raw.output_channels,
+            input_channels: raw.input_channels,
+            duplex_channels: raw.duplex_channels,
+            is_default_output: raw.is_default_output != 0,
+            is_default_input: raw.is_default_input != 0,
+            native_formats: NATIVE_FORMATS.iter()
+                .filter(|(mask, _)| raw.native_formats & mask != 0)
+                .map(|(_, name)| *name).collect(),
+            preferred_sample_rate: raw.preferred_sample_rate,
+            sample_rates: raw.sample_rates.iter()
+                .take_while(|rate| **rate != 0).cloned().collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "device {}: {:?}", self.index, self.name)?;
+        if self.is_default_output { write!(f, " <default output>")?; }
+        if self.is_default_input { write!(f, " <default input>")?; }
+        writeln!(f)?;
+        writeln!(f, "\tmaximum output channels: {},", self.output_channels)?;
+        writeln!(f, "\tmaximum input channels: {},", self.input_channels)?;
+        writeln!(f, "\tmaximum duplex channels: {},", self.duplex_channels)?;
+        writeln!(f, "\tsample rates: {:?},", self.sample_rates)?;
+        writeln!(f, "\tpreferred sample rate: {},", self.preferred_sample_rate)?;
+        write!(f, "\tnative formats: {:?}.", self.native_formats)
+    }
+}
+
+pub fn devices() -> Vec<DeviceInfo> {
+    let count = unsafe { rtaudio_get_devices(std::ptr::null_mut(), 0) };
+    if count <= 0 { return Vec::new(); }
+
+    let mut raw = vec![unsafe { std::mem::zeroed::<RawDeviceInfo>() }; count as usize];
+    let count = unsafe { rtaudio_get_devices(raw.as_mut_ptr(), count) };
+
+    raw.iter().take(std::cmp::max(count, 0) as usize).enumerate()
+        .filter(|(_, device)| device.probed != 0)
+        .map(|(index, device)| DeviceInfo::from_raw(index as u32, device))
+        .collect()
+}
+
+
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum Device {
+    #[default]
+    Default,
+    Index(u32),
+    Name(String),
+}
+
+impl std::str::FromStr for Device {
+    type Err = std::convert::Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name.parse::<u32>() {
+            Ok(index) => Device::Index(index),
+            Err(_) if name == "default" => Device::Default,
+            Err(_) => Device::Name(name.to_owned()),
+        })
+    }
+}
+
+impl Device {
+    fn resolve(&self, input: bool) -> Result<i32, StreamError> {
+        match self {
+            Device::Default => Ok(DEFAULT_DEVICE),
+            Device::Index(index) => Ok(*index as i32),
+            Device::Name(name) => devices().iter()
+                .find(|device| {
+                    let channels = if input {
+                        device.input_channels
+                    } else {
+                        device.output_channels
+                    };
+
+                    device.name == *name && channels > 0
+                })
+                .map(|device| device.index as i32)
+                .ok_or_else(|| StreamError::DeviceNotFound(name.clone())),
+        }
+    }
+}
 
 #[inline(always)]
 unsafe extern "C" fn callback_adapter<F>(data: *mut c_void, buffer: *mut i16, size: usize)
@@ -57,7 +192,8 @@ unsafe fn result_unwrap(result: *mut c_void) -> Result<*mut c_void, StreamError>
     }
 }
 
-pub fn create_output_stream<F>(callback_: F) -> Result<Stream, StreamError>
+pub fn create_output_stream<F>(sample_rate: u32, channels: u32, device: i32, callback_: F)
+                               -> Result<Stream, StreamError>
     where F: FnMut(&mut [i16]) + Send + 'static
 {
     let callback = Box::new(callback_);
@@ -66,11 +202,15 @@ pub fn create_output_stream<F>(callback_: F) -> Result<Stream, StreamError>
         Ok(Stream::new(result_unwrap(rtaudio_create_output_stream(
             callback_adapter::<F> as *const () as _,
             callback.deref() as *const _ as _,
+            sample_rate,
+            channels,
+            device,
         ))?, callback))
     }
 }
 
-pub fn create_input_stream<F>(callback_: F) -> Result<Stream, StreamError>
+pub fn create_input_stream<F>(sample_rate: u32, channels: u32, device: i32, callback_: F)
+                              -> Result<Stream, StreamError>
     where F: FnMut(&mut [i16]) + Send + 'static
 {
     let callback = Box::new(callback_);
@@ -79,6 +219,46 @@ pub fn create_input_stream<F>(callback_: F) -> Result<Stream, StreamError>
         Ok(Stream::new(result_unwrap(rtaudio_create_input_stream(
             callback_adapter::<F> as *const () as _,
             callback.deref() as *const _ as _,
+            sample_rate,
+            channels,
+            device,
         ))?, callback))
     }
 }
+
+#[derive(Clone, Debug, Default)]
+pub struct RtAudio {
+    input: Device,
+    output: Device,
+}
+
+impl RtAudio {
+    pub fn set_input_device(&mut self, device: Device) -> &mut Self {
+        self.input = device;
+        self
+    }
+
+    pub fn set_output_device(&mut self, device: Device) -> &mut Self {
+        self.output = device;
+        self
+    }
+}
+
+impl SampleBackend for RtAudio {
+    type Stream = Stream;
+    type Error = StreamError;
+
+    fn create_input_stream<F>(&self, sample_rate: u32, channels: u32, callback: F)
+                              -> Result<Self::Stream, Self::Error>
+        where F: FnMut(&mut [i16]) + Send + 'static
+    {
+        create_input_stream(sample_rate, channels, self.input.resolve(true)?, callback)
+    }
+
+    fn create_output_stream<F>(&self, sample_rate: u32, channels: u32, callback: F)
+                               -> Result<Self::Stream, Self::Error>
+        where F: FnMut(&mut [i16]) + Send + 'static
+    {
+        create_output_stream(sample_rate, channels, self.output.resolve(false)?, callback)
+    }
+}
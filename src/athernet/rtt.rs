@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+
+const ALPHA: f32 = 1. / 8.;
+const BETA: f32 = 1. / 4.;
+const VARIANCE_SCALE: f32 = 4.;
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct RttEstimate {
+    pub srtt: Duration,
+    pub rto: Duration,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RttEstimator {
+    srtt: Option<f32>,
+    rttvar: f32,
+    rto: usize,
+    minimum: usize,
+    maximum: usize,
+}
+
+impl RttEstimator {
+    pub fn new(initial: usize, minimum: usize, maximum: usize) -> Self {
+        Self { srtt: None, rttvar: 0., rto: initial, minimum, maximum }
+    }
+
+    pub fn sample(&mut self, rtt: usize, retransmitted: bool) {
+        if retransmitted { return; }
+
+        let rtt = rtt as f32;
+
+        match self.srtt {
+            Some(ref mut srtt) => {
+                self.rttvar = (1. - BETA) * self.rttvar + BETA * (*srtt - rtt).abs();
+                *srtt = (1. - ALPHA) * *srtt + ALPHA * rtt;
+            }
+            None => {
+                self.rttvar = rtt / 2.;
+                self.srtt = Some(rtt);
+            }
+        }
+
+        let rto = self.srtt.unwrap() + VARIANCE_SCALE * self.rttvar;
+
+        self.rto = (rto.ceil() as usize).max(self.minimum).min(self.maximum);
+    }
+
+    pub fn backoff(&mut self) {
+        self.rto = std::cmp::min(self.rto * 2, self.maximum);
+    }
+
+    #[inline]
+    pub fn rto(&self) -> usize { self.rto }
+
+    pub fn estimate(&self, sample_rate: u32) -> Option<RttEstimate> {
+        let duration = |samples: f32| {
+            Duration::from_secs_f32(samples / sample_rate as f32)
+        };
+
+        self.srtt.map(|srtt| RttEstimate {
+            srtt: duration(srtt),
+            rto: duration(self.rto as f32),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoothed_update() {
+        let mut estimator = RttEstimator::new(1000, 10, 100000);
+
+        estimator.sample(400, false);
+        assert_eq!((estimator.srtt, estimator.rttvar, estimator.rto()), (Some(400.), 200., 1200));
+
+        estimator.sample(800, false);
+        assert_eq!((estimator.srtt, estimator.rttvar), (Some(450.), 250.));
+        assert_eq!(estimator.rto(), 1450);
+
+        let estimate = estimator.estimate(1000).unwrap();
+        assert!((estimate.srtt.as_secs_f32() - 0.45).abs() < 1e-4);
+        assert!((estimate.rto.as_secs_f32() - 1.45).abs() < 1e-4);
+    }
+
+    #[test]
+    fn karn_ignores_retransmitted_samples() {
+        let mut estimator = RttEstimator::new(1000, 10, 100000);
+        assert!(estimator.estimate(1000).is_none());
+
+        estimator.backoff();
+        estimator.sample(100, true);
+        assert_eq!(estimator.rto(), 2000);
+        assert!(estimator.estimate(1000).is_none());
+
+        estimator.sample(100, false);
+        assert_eq!(estimator.rto(), 300);
+    }
+
+    #[test]
+    fn clamped_rto() {
+        let mut estimator = RttEstimator::new(1000, 500, 4000);
+
+        estimator.sample(10, false);
+        assert_eq!(estimator.rto(), 500);
+
+        estimator.sample(100000, false);
+        assert_eq!(estimator.rto(), 4000);
+
+        for _ in 0..8 { estimator.backoff(); }
+        assert_eq!(estimator.rto(), 4000);
+    }
+}
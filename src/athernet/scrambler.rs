@@ -0,0 +1,157 @@
+// all ones or all zeros would let a constant input lock the self-synchronizing mode
+const LFSR_SEED: u8 = 0b1011001;
+const LFSR_MASK: u8 = 0b1111111;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Scrambler {
+    None,
+    Additive,
+    SelfSync,
+}
+
+impl std::str::FromStr for Scrambler {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "none" => Ok(Scrambler::None),
+            "additive" => Ok(Scrambler::Additive),
+            "self-sync" => Ok(Scrambler::SelfSync),
+            _ => Err(format!("unknown scrambler: {:?}", name)),
+        }
+    }
+}
+
+impl Scrambler {
+    pub fn scramble<I>(self, iter: I) -> Box<dyn Iterator<Item=bool> + Send>
+        where I: Iterator<Item=bool> + Send + 'static
+    {
+        match self {
+            Scrambler::None => Box::new(iter),
+            _ => Box::new(Scramble { iter, lfsr: Lfsr::new(self) }),
+        }
+    }
+}
+
+
+#[derive(Copy, Clone)]
+struct Lfsr {
+    scrambler: Scrambler,
+    state: u8,
+}
+
+impl Lfsr {
+    fn new(scrambler: Scrambler) -> Self { Self { scrambler, state: LFSR_SEED } }
+
+    #[inline]
+    fn feedback(&self) -> bool { ((self.state >> 3) ^ (self.state >> 6)) & 1 == 1 }
+
+    #[inline]
+    fn shift(&mut self, bit: bool) {
+        self.state = ((self.state << 1) | bit as u8) & LFSR_MASK;
+    }
+
+    #[inline]
+    fn scramble(&mut self, bit: bool) -> bool {
+        let feedback = self.feedback();
+        let result = bit ^ feedback;
+
+        match self.scrambler {
+            Scrambler::SelfSync => self.shift(result),
+            _ => self.shift(feedback),
+        }
+
+        result
+    }
+
+    #[inline]
+    fn descramble(&mut self, bit: bool) -> bool {
+        let feedback = self.feedback();
+
+        match self.scrambler {
+            Scrambler::SelfSync => self.shift(bit),
+            _ => self.shift(feedback),
+        }
+
+        bit ^ feedback
+    }
+}
+
+
+struct Scramble<I> {
+    iter: I,
+    lfsr: Lfsr,
+}
+
+impl<I: Iterator<Item=bool>> Iterator for Scramble<I> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bit = self.iter.next()?;
+        Some(self.lfsr.scramble(bit))
+    }
+}
+
+
+#[derive(Copy, Clone)]
+pub struct Descrambler {
+    lfsr: Lfsr,
+}
+
+impl Descrambler {
+    pub fn new(scrambler: Scrambler) -> Self { Self { lfsr: Lfsr::new(scrambler) } }
+
+    pub fn push(&mut self, bit: bool) -> bool {
+        match self.lfsr.scrambler {
+            Scrambler::None => bit,
+            _ => self.lfsr.descramble(bit),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scramble(scrambler: Scrambler, bits: Vec<bool>) -> Vec<bool> {
+        scrambler.scramble(bits.into_iter()).collect()
+    }
+
+    fn descramble(scrambler: Scrambler, bits: &[bool]) -> Vec<bool> {
+        let mut descrambler = Descrambler::new(scrambler);
+        bits.iter().map(|bit| descrambler.push(*bit)).collect()
+    }
+
+    fn longest_run(bits: &[bool]) -> usize {
+        bits.windows(2).fold((1, 1), |(longest, run), pair| {
+            let run = if pair[0] == pair[1] { run + 1 } else { 1 };
+            (std::cmp::max(longest, run), run)
+        }).0
+    }
+
+    #[test]
+    fn round_trip() {
+        let bits = (0..1000).map(|index| (index * 11) % 5 < 2).collect::<Vec<_>>();
+
+        for scrambler in [Scrambler::None, Scrambler::Additive, Scrambler::SelfSync].iter() {
+            let scrambled = scramble(*scrambler, bits.clone());
+            assert_eq!(descramble(*scrambler, &scrambled), bits, "{:?}", scrambler);
+        }
+    }
+
+    #[test]
+    fn whitens_constant_bytes() {
+        for byte in [false, true].iter() {
+            let bits = vec![*byte; 1024];
+
+            for scrambler in [Scrambler::Additive, Scrambler::SelfSync].iter() {
+                let scrambled = scramble(*scrambler, bits.clone());
+                let ones = scrambled.iter().filter(|bit| **bit).count();
+
+                assert!(ones > 448 && ones < 576, "{:?} {} ones {}", scrambler, byte, ones);
+                assert!(longest_run(&scrambled) <= 8, "{:?} {}", scrambler, byte);
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use crate::athernet::{
+    channel::Channel, mac::MacFrame, physical::{modulate, Demodulator, PhyConfig},
+};
+
+
+pub const SEEDS: usize = 4;
+const PADDING: usize = 3000;
+
+
+pub fn payload(size: usize) -> Vec<u8> {
+    (0..size).map(|item| (item * 13 + 5) as u8).collect()
+}
+
+pub fn trace(config: &PhyConfig, payload: &[u8], scale: i16) -> Vec<i16> {
+    let frame = MacFrame::new_data(1, 2, 0, payload);
+
+    let mut samples = vec![0; PADDING];
+    samples.extend(modulate(frame, config).map(|item| item / scale));
+    samples.resize(samples.len() + PADDING, 0);
+    samples
+}
+
+pub fn drift(samples: &[i16], ppm: f64, offset: f64) -> impl Iterator<Item=f32> + '_ {
+    let step = 1. + ppm * 1e-6;
+
+    (0..).map(move |index| offset + index as f64 * step)
+        .take_while(move |time| (*time as usize) + 1 < samples.len())
+        .map(move |time| {
+            let (index, fraction) = (time as usize, time.fract() as f32);
+            samples[index] as f32 * (1. - fraction) + samples[index + 1] as f32 * fraction
+        })
+}
+
+pub fn receive<I: Iterator<Item=f32>>(config: &PhyConfig, channel: &mut Channel, samples: I)
+                                      -> Option<Box<[u8]>> {
+    let mut demodulator = Demodulator::new(2, config.clone());
+
+    samples.filter_map(|sample| demodulator.push_back(channel.push_back(sample)))
+        .find(|(frame, _)| frame.check(2))
+        .map(|(frame, _)| frame.unwrap())
+}
+
+pub fn received(config: &PhyConfig, spec: &str, size: usize, ppm: f64) -> usize {
+    let payload = payload(size);
+    let samples = trace(config, &payload, 1);
+
+    (0..SEEDS).filter(|seed| {
+        let spec = format!("seed:{},{}", seed, spec);
+        let mut channel = Channel::parse(&spec, config).unwrap();
+        let samples = drift(&samples, ppm, *seed as f64 * 0.1);
+
+        receive(config, &mut channel, samples).as_deref() == Some(&payload[..])
+    }).count()
+}
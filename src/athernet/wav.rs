@@ -0,0 +1,212 @@
+use std::{
+    fs::File, path::Path, sync::{Arc, Mutex},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+};
+use crate::{
+    athernet::backend::{SampleBackend, SAMPLE_RATE},
+    utils::{slice_to_le_u16, slice_to_le_u32},
+};
+
+
+const HEADER_LEN: usize = 44;
+const FORMAT_PCM: u16 = 1;
+const BITS_PER_SAMPLE: u16 = 16;
+
+
+fn invalid_data(message: &str) -> io::Error { io::Error::new(ErrorKind::InvalidData, message) }
+
+pub fn read_channels<P: AsRef<Path>>(path: P) -> io::Result<(Vec<Vec<i16>>, u32)> {
+    let mut data = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err(invalid_data("not a wav file"));
+    }
+
+    let mut format = None;
+    let mut offset = 12;
+
+    while offset + 8 <= data.len() {
+        let id = &data[offset..][..4];
+        let size = slice_to_le_u32(&data[offset + 4..][..4]) as usize;
+        let body = &data[offset + 8..][..std::cmp::min(size, data.len() - offset - 8)];
+
+        match id {
+            b"fmt " => {
+                if body.len() < 16 { return Err(invalid_data("broken fmt chunk")); }
+
+                if slice_to_le_u16(&body[0..2]) != FORMAT_PCM ||
+                    slice_to_le_u16(&body[14..16]) != BITS_PER_SAMPLE {
+                    return Err(invalid_data("only 16 bit pcm is supported"));
+                }
+
+                let channels = slice_to_le_u16(&body[2..4]) as usize;
+                if channels == 0 { return Err(invalid_data("no channels")); }
+
+                format = Some((channels, slice_to_le_u32(&body[4..8])));
+            }
+            b"data" => {
+                let (channels, sample_rate) = format
+                    .ok_or_else(|| invalid_data("data before fmt chunk"))?;
+
+                return Ok(((0..channels).map(|channel| {
+                    body.chunks_exact(2 * channels)
+                        .map(|frame| slice_to_le_u16(&frame[2 * channel..][..2]) as i16)
+                        .collect()
+                }).collect(), sample_rate));
+            }
+            _ => {}
+        }
+
+        offset += 8 + size + size % 2;
+    }
+
+    Err(invalid_data("missing data chunk"))
+}
+
+pub fn read_pcm<P: AsRef<Path>>(path: P) -> io::Result<Vec<i16>> {
+    let mut data = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut data)?;
+
+    Ok(data.chunks_exact(2).map(|sample| slice_to_le_u16(sample) as i16).collect())
+}
+
+
+pub struct WavWriter {
+    file: BufWriter<File>,
+    count: u32,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut result = Self {
+            file: BufWriter::new(File::create(path)?),
+            count: 0,
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+        };
+        result.write_header()?;
+        Ok(result)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let data_size = self.count * 2;
+        let mut header = [0u8; HEADER_LEN];
+
+        header[0..4].copy_from_slice(b"RIFF");
+        header[4..8].copy_from_slice(&(data_size + HEADER_LEN as u32 - 8).to_le_bytes());
+        header[8..12].copy_from_slice(b"WAVE");
+        header[12..16].copy_from_slice(b"fmt ");
+        header[16..20].copy_from_slice(&16u32.to_le_bytes());
+        header[20..22].copy_from_slice(&FORMAT_PCM.to_le_bytes());
+        header[22..24].copy_from_slice(&self.channels.to_le_bytes());
+        header[24..28].copy_from_slice(&self.sample_rate.to_le_bytes());
+        header[28..32].copy_from_slice(
+            &(self.sample_rate * 2 * self.channels as u32).to_le_bytes()
+        );
+        header[32..34].copy_from_slice(&(2 * self.channels).to_le_bytes());
+        header[34..36].copy_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+        header[36..40].copy_from_slice(b"data");
+        header[40..44].copy_from_slice(&data_size.to_le_bytes());
+
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.file.seek(SeekFrom::End(0))?;
+
+        Ok(())
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) -> &mut Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn set_channels(&mut self, channels: u16) -> &mut Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn write(&mut self, data: &[i16]) -> io::Result<()> {
+        for sample in data.iter() {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+
+        self.count += data.len() as u32;
+
+        Ok(())
+    }
+
+    pub fn finalize(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.file.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) { let _ = self.finalize(); }
+}
+
+
+pub struct Recorder<B> {
+    backend: B,
+    writer: Arc<Mutex<WavWriter>>,
+}
+
+impl<B: SampleBackend> Recorder<B> {
+    pub fn new<P: AsRef<Path>>(backend: B, path: P) -> io::Result<Self> {
+        Ok(Self { backend, writer: Arc::new(Mutex::new(WavWriter::create(path)?)) })
+    }
+}
+
+impl<B: SampleBackend> SampleBackend for Recorder<B> {
+    type Stream = B::Stream;
+    type Error = B::Error;
+
+    fn create_input_stream<F>(&self, sample_rate: u32, channels: u32, mut callback: F)
+                              -> Result<Self::Stream, Self::Error>
+        where F: FnMut(&mut [i16]) + Send + 'static
+    {
+        let writer = self.writer.clone();
+        writer.lock().unwrap().set_sample_rate(sample_rate).set_channels(channels as u16);
+
+        self.backend.create_input_stream(sample_rate, channels, move |data: &mut [i16]| {
+            let _ = writer.lock().unwrap().write(data);
+            callback(data);
+        })
+    }
+
+    fn create_output_stream<F>(&self, sample_rate: u32, channels: u32, callback: F)
+                               -> Result<Self::Stream, Self::Error>
+        where F: FnMut(&mut [i16]) + Send + 'static
+    {
+        self.backend.create_output_stream(sample_rate, channels, callback)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("athernet-{}.wav", std::process::id()));
+        let left = (0..1000).map(|item| (item * 37 - 18000) as i16).collect::<Vec<_>>();
+        let right = left.iter().map(|item| item.wrapping_neg()).collect::<Vec<_>>();
+
+        let mut writer = WavWriter::create(&path).unwrap();
+        writer.set_sample_rate(44100).set_channels(2);
+
+        for (left, right) in left.iter().zip(right.iter()) {
+            writer.write(&[*left, *right]).unwrap();
+        }
+        drop(writer);
+
+        let result = read_channels(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(result.unwrap(), (vec![left, right], 44100));
+    }
+}
@@ -6,40 +6,76 @@ pub mod wire;
 extern crate lazy_static;
 
 
-use std::{env, fs::File, io::{Read, BufReader, Write}};
-use crate::{athernet::{MacLayer, mac::{MAC_PAYLOAD_MAX, MacPayload}}, utils::slice_to_le_u64};
+use std::{collections::VecDeque, env, fs::File, io::{Read, BufReader, Write}};
+use crate::{
+    athernet::{
+        MacLayer, RtAudio, Device, backend::SampleBackend, fec::Fec, devices, loopback::Loopback,
+        channel::Channel,
+        physical::{Demodulator, PhyConfig},
+        mac::{MacFrame, MacConfig}, wav::{self, Recorder},
+    },
+    utils::slice_to_le_u64,
+};
+
+
+const USAGE: &str = "\
+usage: network <address> [options]
+       network decode <file> [options]
+       network -d
+
+commands:
+  -s <dest> <file>   send a file to dest
+  -r <src> <file>    receive a file from src
+  -p <dest>          ping dest 256 times
+  -w <seconds>       wait before exiting
+
+audio:
+  -d                 list audio devices
+  -i <device>        input device, by index, name or \"default\"
+  -j <device>        output device, by index, name or \"default\"
+  -c <file.wav>      record the input to a wav file
+  -v <file.wav>      replay a recording instead of using the sound card
+  -q <channel>       decode through a simulated channel, e.g. snr:10,delay:400,echo:120/0.3
+  -e                 print thresholds and throughput every second, rtt after sends
+
+physical layer:
+  -m <modulation>    bpsk, qpsk, dqpsk or ofdm
+  -l <samples>       symbol length
+  -g <shape>         pulse shape: rect, sinc, rc or rrc
+  -o <roll-off>      roll-off of rc and rrc pulses
+  -n <symbols>       pulse span
+  -k <hz>            passband carrier frequency
+  -b <index>         frequency band, overrides -k
+  -z <hz>            sample rate
+  -t <count>         audio channels, one link per channel
+  -f                 hamming forward error correction
+  -x <scrambler>     none, additive or self-sync
+  -u <bytes>         maximum frame payload, header and crc included
+
+mac layer:
+  -a <frames>        sliding window size, at most 8
+  -y <count>         retransmissions before a frame is dropped
+";
 
 
 pub struct FileRead<T> {
     iter: T,
+    size: usize,
 }
 
 impl<T> FileRead<T> {
-    pub fn new(iter: T) -> Self {
-        Self { iter }
+    pub fn new(iter: T, size: usize) -> Self {
+        Self { iter, size }
     }
 }
 
 impl<T: Iterator<Item=u8>> Iterator for FileRead<T> {
-    type Item = MacPayload;
+    type Item = Vec<u8>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut ret = [0; MAC_PAYLOAD_MAX];
-        let mut size = MAC_PAYLOAD_MAX - 1;
-
-        for i in 0..MAC_PAYLOAD_MAX - 1 {
-            if let Some(byte) = self.iter.next() {
-                ret[i + 1] = byte;
-            } else {
-                if i == 0 { return None; }
-                size = i;
-                break;
-            }
-        }
-
-        ret[0] = size as u8;
+        let ret = self.iter.by_ref().take(self.size).collect::<Vec<_>>();
 
-        Some(ret)
+        if ret.is_empty() { None } else { Some(ret) }
     }
 }
 
@@ -49,49 +85,9 @@ enum Command {
     Ping(u8),
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // let _ = IPV4Layer::new([192, 168, 0, 1], 4);
-
-    let mut args = env::args();
-
-    args.next();
-
-    let src = args.next().unwrap().parse::<u8>()? & 0b1111;
-    let mut commands = Vec::new();
-    let mut perf = false;
-    let mut wait = 0;
-
-    while let Some(command_) = args.next() {
-        let command = command_.as_bytes();
-
-        if command[0] as char != '-' || command.len() != 2 {
-            return Err(String::from(
-                format!("unknown command: {:?}", command_).to_owned()
-            ).into());
-        }
-
-        match command[1] as char {
-            'e' => perf = true,
-            's' => {
-                commands.push(Command::Send(
-                    args.next().unwrap().parse::<u8>()?, args.next().unwrap(),
-                ))
-            }
-            'r' => {
-                commands.push(Command::Recv(
-                    args.next().unwrap().parse::<u8>()?, args.next().unwrap(),
-                ))
-            }
-            'p' => commands.push(Command::Ping(args.next().unwrap().parse::<u8>()?)),
-            'w' => wait = args.next().unwrap().parse::<u64>()?,
-            _ => {
-                Err(format!("command {:?} need parameter!", command))?;
-            }
-        }
-    }
-
-    let mut athernet = MacLayer::new(src, perf)?;
-
+fn execute<B: SampleBackend>(
+    athernet: &mut MacLayer<B>, commands: Vec<Command>, wait: u64, perf: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
     for command in commands {
         match command {
             Command::Send(dest, name) => {
@@ -101,17 +97,29 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
                 println!("sending {:?}, size {}", name, size);
 
-                let mut buffer = [0; MAC_PAYLOAD_MAX];
-                buffer[0] = 8;
-                buffer[1..9].copy_from_slice(&size.to_le_bytes());
+                let mut deliveries = VecDeque::new();
 
-                athernet.send(&buffer, dest)?;
+                deliveries.push_back(athernet.send(&size.to_le_bytes(), dest)?);
 
                 let iter = BufReader::new(file)
                     .bytes().filter_map(|item| item.ok());
 
-                for data_pack in FileRead::new(iter) {
-                    athernet.send(&data_pack, dest)?;
+                for data_pack in FileRead::new(iter, athernet.get_mtu()) {
+                    deliveries.push_back(athernet.send(&data_pack, dest)?);
+
+                    while let Some(delivered) = deliveries.front()
+                        .and_then(|item| item.try_wait()) {
+                        if !delivered { Err(format!("delivery to {} failed", dest))?; }
+                        deliveries.pop_front();
+                    }
+                }
+
+                if !deliveries.iter().all(|item| item.wait()) {
+                    Err(format!("delivery to {} failed", dest))?;
+                }
+
+                if let (true, Some(rtt)) = (perf, athernet.get_rtt(dest)) {
+                    println!("rtt {:?}, rto {:?}", rtt.srtt, rtt.rto);
                 }
             }
             Command::Recv(dest, name) => {
@@ -147,3 +155,179 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+fn decode(
+    name: String, config: PhyConfig, channel: Option<Channel>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    config.validate()?;
+
+    let channels = if name.ends_with(".wav") {
+        let (channels, sample_rate) = wav::read_channels(name)?;
+
+        if sample_rate != config.sample_rate {
+            Err(format!("sample rate mismatch: {} != {}", sample_rate, config.sample_rate))?;
+        }
+
+        channels
+    } else {
+        let samples = wav::read_pcm(name)?;
+        let count = std::cmp::max(config.channels, 1);
+
+        (0..count).map(|channel| {
+            samples.iter().skip(channel).step_by(count).cloned().collect()
+        }).collect()
+    };
+
+    for (index, samples) in channels.iter().enumerate() {
+        if channels.len() > 1 { println!("channel {}", index); }
+
+        let mut demodulator = Demodulator::new(MacFrame::BROADCAST_MAC, config.clone());
+        let mut channel = channel.as_ref().map(|channel| {
+            let mut channel = channel.fork(index as u64);
+            channel.set_signal_power(Channel::measure_power(samples, config.symbol_len * 16));
+            channel
+        });
+
+        for (offset, sample) in samples.iter().enumerate() {
+            let sample = match channel {
+                Some(ref mut channel) => channel.push_back(*sample as f32),
+                None => *sample,
+            };
+
+            if let Some((frame, info)) = demodulator.push_back(sample) {
+                println!("{}: {:?}", offset, frame);
+                println!("\t{}; {}", info, demodulator.thresholds());
+            }
+
+            if let Some((start, reason)) = demodulator.take_aborted() {
+                println!("{}: aborted {:?}, started at {}", offset, reason, start);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // let _ = IPV4Layer::new([192, 168, 0, 1], 4);
+
+    let mut args = env::args().skip(1).peekable();
+
+    if args.peek().is_none() {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+
+    let decode_name = if args.peek().map(String::as_str) == Some("decode") {
+        args.next();
+        Some(args.next().ok_or("decode needs a file name, see -h")?)
+    } else {
+        None
+    };
+
+    let address = match args.peek() {
+        Some(item) if decode_name.is_none() && !item.starts_with('-') => args.next(),
+        _ => None,
+    };
+
+    let mut commands = Vec::new();
+    let mut perf = false;
+    let mut wait = 0;
+    let mut capture = None;
+    let mut replay = None;
+    let mut channel = None;
+    let mut list = false;
+    let mut backend = RtAudio::default();
+    let mut config = PhyConfig::default();
+    let mut mac_config = MacConfig::default();
+
+    while let Some(command_) = args.next() {
+        let command = command_.as_bytes();
+
+        if command.len() != 2 || command[0] as char != '-' {
+            return Err(format!("unknown command: {:?}, see -h", command_).into());
+        }
+
+        let mut param = || {
+            args.next().ok_or_else(|| format!("{} needs a parameter, see -h", command_))
+        };
+
+        match command[1] as char {
+            'h' => {
+                print!("{}", USAGE);
+                return Ok(());
+            }
+            'e' => perf = true,
+            's' => commands.push(Command::Send(param()?.parse::<u8>()?, param()?)),
+            'r' => commands.push(Command::Recv(param()?.parse::<u8>()?, param()?)),
+            'p' => commands.push(Command::Ping(param()?.parse::<u8>()?)),
+            'w' => wait = param()?.parse::<u64>()?,
+            'c' => capture = Some(param()?),
+            'v' => replay = Some(param()?),
+            'q' => channel = Some(param()?),
+            'd' => list = true,
+            'i' => { backend.set_input_device(param()?.parse::<Device>()?); }
+            'j' => { backend.set_output_device(param()?.parse::<Device>()?); }
+            'f' => config.fec = Fec::Hamming,
+            'x' => config.scrambler = param()?.parse()?,
+            'm' => config.modulation = param()?.parse()?,
+            'l' => config.symbol_len = param()?.parse::<usize>()?,
+            'g' => config.pulse_shape = param()?.parse()?,
+            'o' => config.roll_off = param()?.parse::<f32>()?,
+            'n' => config.pulse_span = param()?.parse::<usize>()?,
+            'k' => config.carrier_frequency = Some(param()?.parse::<f32>()?),
+            'b' => config.band = Some(param()?.parse::<usize>()?),
+            'z' => config.sample_rate = param()?.parse::<u32>()?,
+            't' => config.channels = param()?.parse::<usize>()?,
+            'a' => mac_config.window_size = param()?.parse::<usize>()?,
+            'y' => mac_config.max_retries = param()?.parse::<usize>()?,
+            'u' => config.payload_max = param()?.parse::<usize>()?,
+            _ => {
+                Err(format!("unknown command: {:?}, see -h", command_))?;
+            }
+        }
+    }
+
+    let channel = channel.map(|spec| Channel::parse(&spec, &config)).transpose()?;
+
+    if let Some(name) = decode_name { return decode(name, config, channel); }
+
+    if list {
+        for device in devices() { println!("{}", device); }
+        return Ok(());
+    }
+
+    let src = address
+        .ok_or("missing mac address, see -h")?
+        .parse::<u8>()?;
+
+    if src == MacFrame::BROADCAST_MAC { Err(format!("{} is the broadcast address", src))?; }
+
+    if let Some(path) = replay {
+        let (channels, sample_rate) = wav::read_channels(path)?;
+        let count = std::cmp::max(config.channels, 1);
+
+        if sample_rate != config.sample_rate || channels.len() != count {
+            Err(format!(
+                "recording has {} channels at {} Hz, expected {} at {} Hz",
+                channels.len(), sample_rate, count, config.sample_rate,
+            ))?;
+        }
+
+        let samples = (0..channels[0].len())
+            .flat_map(|index| channels.iter().map(move |channel| channel[index]))
+            .collect::<Vec<_>>();
+
+        let backend = Loopback::new();
+        let mut athernet = MacLayer::with_config(&backend, src, perf, config, mac_config)?;
+        backend.play(&samples);
+        execute(&mut athernet, commands, wait, perf)
+    } else if let Some(path) = capture {
+        let backend = Recorder::new(backend, path)?;
+        let mut athernet = MacLayer::with_config(&backend, src, perf, config, mac_config)?;
+        execute(&mut athernet, commands, wait, perf)
+    } else {
+        let mut athernet = MacLayer::with_config(&backend, src, perf, config, mac_config)?;
+        execute(&mut athernet, commands, wait, perf)
+    }
+}
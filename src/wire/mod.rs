@@ -3,7 +3,7 @@ pub mod udp;
 
 
 use std::fmt::Formatter;
-use crate::athernet::MacLayer;
+use crate::athernet::{MacLayer, physical::PhyConfig};
 
 
 pub struct IPV4Datagram(Box<[u8]>);
@@ -174,7 +174,9 @@ impl IPV4Layer {
     pub fn new(
         ip_address: ipv4::Address, mac_address: u8,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(Self { ip_address, mac_layer: MacLayer::new(mac_address, false)? })
+        let mac_layer = MacLayer::new(mac_address, false, PhyConfig::default())?;
+
+        Ok(Self { ip_address, mac_layer })
     }
 
     pub fn send(